@@ -0,0 +1,358 @@
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+use async_trait::async_trait;
+use regex::Regex;
+use tokio::sync::broadcast;
+use crate::db::AutomationStep;
+use crate::ssh::expect::{self, ExpectBuffer};
+use crate::ssh::{self, SSHError};
+use super::AutomationError;
+
+/// How long an `Expect` step waits when it doesn't say
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+/// Steps one run may take, so a `goto` loop can't spin forever
+const MAX_STEPS: usize = 10_000;
+
+/// A script with its patterns compiled and labels resolved. Ops line up
+/// with the script's steps, so errors and log entries can point at them.
+pub struct Program {
+    ops: Vec<Op>,
+}
+
+enum Op {
+    Send { text: String, enter: bool },
+    Expect { patterns: Vec<Regex>, branches: Vec<Branch>, timeout: Duration, on_timeout: Option<usize> },
+    /// Only a jump target
+    Label,
+    Goto(usize),
+    Sleep(Duration),
+    Fail(String),
+}
+
+struct Branch {
+    capture: Option<String>,
+    goto: Option<usize>,
+}
+
+impl Program {
+    /// Check the whole script up front, so a typo in a late step doesn't
+    /// leave a tab halfway through it
+    pub fn compile(steps: &[AutomationStep]) -> Result<Self, AutomationError> {
+        let mut labels = HashMap::new();
+        for (index, step) in steps.iter().enumerate() {
+            if let AutomationStep::Label { name } = step {
+                if labels.insert(name.as_str(), index).is_some() {
+                    return Err(step_error(index, format!("Label {} is defined twice", name)));
+                }
+            }
+        }
+        let resolve = |index: usize, label: &str| {
+            labels.get(label).copied().ok_or_else(|| step_error(index, format!("No label {}", label)))
+        };
+
+        let mut ops = Vec::with_capacity(steps.len());
+        for (index, step) in steps.iter().enumerate() {
+            ops.push(match step {
+                AutomationStep::Send { text, enter } => Op::Send { text: text.clone(), enter: *enter },
+                AutomationStep::Expect { branches, timeout_ms, on_timeout } => {
+                    if branches.is_empty() {
+                        return Err(step_error(index, "Expect step has no patterns".to_string()));
+                    }
+                    let mut patterns = Vec::with_capacity(branches.len());
+                    let mut targets = Vec::with_capacity(branches.len());
+                    for branch in branches {
+                        let pattern = Regex::new(&branch.pattern)
+                            .map_err(|e| step_error(index, format!("Invalid pattern /{}/: {}", branch.pattern, e)))?;
+                        patterns.push(pattern);
+                        targets.push(Branch {
+                            capture: branch.capture.clone(),
+                            goto: branch.goto.as_deref().map(|label| resolve(index, label)).transpose()?,
+                        });
+                    }
+                    Op::Expect {
+                        patterns,
+                        branches: targets,
+                        timeout: timeout_ms.map(Duration::from_millis).unwrap_or(DEFAULT_TIMEOUT),
+                        on_timeout: on_timeout.as_deref().map(|label| resolve(index, label)).transpose()?,
+                    }
+                }
+                AutomationStep::Label { .. } => Op::Label,
+                AutomationStep::Goto { label } => Op::Goto(resolve(index, label)?),
+                AutomationStep::Sleep { ms } => Op::Sleep(Duration::from_millis(*ms)),
+                AutomationStep::Fail { message } => Op::Fail(message.clone()),
+            });
+        }
+
+        Ok(Self { ops })
+    }
+}
+
+/// Where a run's input goes
+#[async_trait]
+pub trait Input: Send {
+    async fn send(&mut self, data: &str) -> Result<(), SSHError>;
+}
+
+/// Types into an open tab, like the user would
+pub struct TabInput(pub String);
+
+#[async_trait]
+impl Input for TabInput {
+    async fn send(&mut self, data: &str) -> Result<(), SSHError> {
+        ssh::send_input(&self.0, data).await
+    }
+}
+
+/// Run `program` to its end against a tab's `output`. Matches are stored
+/// in `variables`, and `log` is told what each step did.
+pub async fn execute(
+    program: &Program,
+    input: &mut impl Input,
+    output: &mut broadcast::Receiver<Vec<u8>>,
+    variables: &mut BTreeMap<String, String>,
+    mut log: impl FnMut(usize, String),
+) -> Result<(), AutomationError> {
+    let mut buffer = ExpectBuffer::default();
+    let mut index = 0;
+
+    for _ in 0..MAX_STEPS {
+        let Some(op) = program.ops.get(index) else { return Ok(()) };
+        let mut next = index + 1;
+
+        match op {
+            Op::Send { text, enter } => {
+                let mut data = interpolate(text, variables).map_err(|message| step_error(index, message))?;
+                if *enter {
+                    data.push('\r');
+                }
+                input.send(&data).await.map_err(|e| step_error(index, e.to_string()))?;
+                // The unexpanded text, so captured secrets stay out of the log
+                log(index, format!("Sent {:?}", text));
+            }
+            Op::Expect { patterns, branches, timeout, on_timeout } => {
+                let found = expect::wait_for(output, &mut buffer, patterns, *timeout)
+                    .await
+                    .map_err(|e| step_error(index, e.to_string()))?;
+
+                match (found, on_timeout) {
+                    (Some((matched, found)), _) => {
+                        let branch = &branches[matched];
+                        // Names only, like `Send`: a capture may be a secret
+                        let mut captured: Vec<String> = found.named.keys().cloned().collect();
+                        captured.sort();
+                        if let Some(name) = &branch.capture {
+                            variables.insert(name.clone(), found.text);
+                            captured.insert(0, name.clone());
+                        }
+                        variables.extend(found.named);
+                        let mut message = format!("Matched /{}/", patterns[matched]);
+                        if !captured.is_empty() {
+                            message.push_str(&format!(", captured {}", captured.join(", ")));
+                        }
+                        log(index, message);
+                        if let Some(target) = branch.goto {
+                            next = target;
+                        }
+                    }
+                    (None, Some(target)) => {
+                        log(index, format!("Timed out after {:?}", timeout));
+                        next = *target;
+                    }
+                    (None, None) => {
+                        return Err(step_error(index, format!("Timed out after {:?} waiting for {}", timeout, describe(patterns))));
+                    }
+                }
+            }
+            Op::Label => {}
+            Op::Goto(target) => {
+                log(index, format!("Going to step {}", target + 1));
+                next = *target;
+            }
+            Op::Sleep(duration) => {
+                tokio::time::sleep(*duration).await;
+                log(index, format!("Waited {:?}", duration));
+            }
+            Op::Fail(message) => {
+                return Err(step_error(index, interpolate(message, variables).unwrap_or_else(|_| message.clone())));
+            }
+        }
+        index = next;
+    }
+
+    if index >= program.ops.len() {
+        return Ok(());
+    }
+    Err(step_error(index, format!("Stopped after {} steps; does a goto loop forever?", MAX_STEPS)))
+}
+
+/// Replace each `${name}` in `text` with that variable
+fn interpolate(text: &str, variables: &BTreeMap<String, String>) -> Result<String, String> {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find("${") {
+        result.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let end = after.find('}').ok_or_else(|| format!("Unclosed ${{ in {:?}", text))?;
+        let name = &after[..end];
+        result.push_str(variables.get(name).ok_or_else(|| format!("No variable {}", name))?);
+        rest = &after[end + 1..];
+    }
+
+    result.push_str(rest);
+    Ok(result)
+}
+
+fn describe(patterns: &[Regex]) -> String {
+    patterns.iter().map(|p| format!("/{}/", p)).collect::<Vec<_>>().join(" or ")
+}
+
+fn step_error(step: usize, message: String) -> AutomationError {
+    AutomationError::Step { step, message }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::ExpectBranch;
+
+    /// A shell that answers known commands on the output channel
+    struct FakeShell {
+        output: broadcast::Sender<Vec<u8>>,
+        replies: HashMap<&'static str, &'static str>,
+        sent: Vec<String>,
+    }
+
+    #[async_trait]
+    impl Input for FakeShell {
+        async fn send(&mut self, data: &str) -> Result<(), SSHError> {
+            self.sent.push(data.to_string());
+            if let Some(reply) = self.replies.get(data) {
+                let _ = self.output.send(reply.as_bytes().to_vec());
+            }
+            Ok(())
+        }
+    }
+
+    fn expect(patterns: &[(&str, Option<&str>, Option<&str>)]) -> AutomationStep {
+        AutomationStep::Expect {
+            branches: patterns
+                .iter()
+                .map(|(pattern, capture, goto)| ExpectBranch {
+                    pattern: pattern.to_string(),
+                    capture: capture.map(str::to_string),
+                    goto: goto.map(str::to_string),
+                })
+                .collect(),
+            timeout_ms: Some(50),
+            on_timeout: None,
+        }
+    }
+
+    fn send(text: &str) -> AutomationStep {
+        AutomationStep::Send { text: text.to_string(), enter: true }
+    }
+
+    #[test]
+    fn test_compile_checks_labels_and_patterns() {
+        let steps = vec![AutomationStep::Goto { label: "done".to_string() }];
+        assert!(matches!(Program::compile(&steps), Err(AutomationError::Step { step: 0, .. })));
+
+        let steps = vec![send("ls"), expect(&[("(unclosed", None, None)])];
+        assert!(matches!(Program::compile(&steps), Err(AutomationError::Step { step: 1, .. })));
+
+        let steps = vec![
+            AutomationStep::Label { name: "top".to_string() },
+            AutomationStep::Label { name: "top".to_string() },
+        ];
+        assert!(Program::compile(&steps).is_err());
+    }
+
+    #[test]
+    fn test_interpolate() {
+        let variables = BTreeMap::from([("user".to_string(), "deploy".to_string())]);
+        assert_eq!(interpolate("sudo -u ${user} -i", &variables).unwrap(), "sudo -u deploy -i");
+        assert_eq!(interpolate("cost: $5 {x}", &variables).unwrap(), "cost: $5 {x}");
+        assert!(interpolate("${host}", &variables).is_err());
+        assert!(interpolate("${user", &variables).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_branches_and_captures() {
+        let (tx, mut rx) = broadcast::channel(16);
+        let mut shell = FakeShell {
+            output: tx.clone(),
+            replies: HashMap::from([
+                ("cat /etc/debian_version\r", "12.5\n$ "),
+                ("echo debian 12.5\r", "debian 12.5\n$ "),
+            ]),
+            sent: Vec::new(),
+        };
+        let program = Program::compile(&[
+            expect(&[(r"\$ $", None, None)]),
+            send("cat /etc/debian_version"),
+            expect(&[
+                (r"No such file", None, Some("other")),
+                (r"(?P<version>\d+\.\d+)\n", Some("line"), None),
+            ]),
+            send("echo debian ${version}"),
+            expect(&[(r"\$ $", None, Some("end"))]),
+            AutomationStep::Label { name: "other".to_string() },
+            send("uname"),
+            AutomationStep::Label { name: "end".to_string() },
+        ])
+        .unwrap();
+
+        tx.send(b"\x1b[01;32mdeploy@web\x1b[00m:~$ ".to_vec()).unwrap();
+        let mut variables = BTreeMap::new();
+        let mut log = Vec::new();
+        execute(&program, &mut shell, &mut rx, &mut variables, |step, message| log.push((step, message)))
+            .await
+            .unwrap();
+
+        assert_eq!(shell.sent, vec!["cat /etc/debian_version\r", "echo debian 12.5\r"]);
+        assert_eq!(variables["version"], "12.5");
+        assert_eq!(variables["line"], "12.5\n");
+        assert_eq!(log.iter().map(|(step, _)| *step).collect::<Vec<_>>(), vec![0, 1, 2, 3, 4]);
+        assert_eq!(log[2].1, r"Matched /(?P<version>\d+\.\d+)\n/, captured line, version");
+        assert!(log.iter().all(|(_, message)| !message.contains("12.5")));
+    }
+
+    #[tokio::test]
+    async fn test_timeout_fails_or_jumps() {
+        let (tx, mut rx) = broadcast::channel(16);
+        let mut shell = FakeShell { output: tx, replies: HashMap::new(), sent: Vec::new() };
+
+        let program = Program::compile(&[expect(&[("never", None, None)])]).unwrap();
+        let result = execute(&program, &mut shell, &mut rx, &mut BTreeMap::new(), |_, _| {}).await;
+        assert!(matches!(result, Err(AutomationError::Step { step: 0, .. })));
+
+        let program = Program::compile(&[
+            AutomationStep::Expect {
+                branches: vec![ExpectBranch { pattern: "never".to_string(), capture: None, goto: None }],
+                timeout_ms: Some(10),
+                on_timeout: Some("fallback".to_string()),
+            },
+            AutomationStep::Fail { message: "unreachable".to_string() },
+            AutomationStep::Label { name: "fallback".to_string() },
+            send("exit"),
+        ])
+        .unwrap();
+        execute(&program, &mut shell, &mut rx, &mut BTreeMap::new(), |_, _| {}).await.unwrap();
+        assert_eq!(shell.sent, vec!["exit\r"]);
+    }
+
+    #[tokio::test]
+    async fn test_endless_loop_is_stopped() {
+        let (tx, mut rx) = broadcast::channel(16);
+        let mut shell = FakeShell { output: tx, replies: HashMap::new(), sent: Vec::new() };
+        let program = Program::compile(&[
+            AutomationStep::Label { name: "again".to_string() },
+            AutomationStep::Goto { label: "again".to_string() },
+        ])
+        .unwrap();
+
+        let result = execute(&program, &mut shell, &mut rx, &mut BTreeMap::new(), |_, _| {}).await;
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,263 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use chrono::Utc;
+use serde::Serialize;
+use tauri::{AppHandle, Emitter};
+use tokio::sync::broadcast;
+use tokio::task::AbortHandle;
+use crate::db::AutomationScript;
+use crate::ssh::{self, SSHError};
+use self::engine::{Program, TabInput};
+
+pub mod engine;
+
+#[derive(Debug, thiserror::Error)]
+pub enum AutomationError {
+    #[error(transparent)]
+    Ssh(#[from] SSHError),
+    /// `step` counts from 0; the message shows it counting from 1
+    #[error("Step {}: {}", .step + 1, .message)]
+    Step { step: usize, message: String },
+    #[error("No automation run {0}")]
+    NotFound(String),
+}
+
+impl AutomationError {
+    /// Stable identifier for the UI, like `SSHError::code`
+    pub fn code(&self) -> &'static str {
+        match self {
+            AutomationError::Ssh(e) => e.code(),
+            AutomationError::Step { .. } => "automation_step",
+            AutomationError::NotFound(_) => "not_found",
+        }
+    }
+}
+
+/// `{ code, message }`, plus `step` for a failed step; SSH errors keep their own shape
+impl Serialize for AutomationError {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeMap;
+
+        if let AutomationError::Ssh(e) = self {
+            return e.serialize(serializer);
+        }
+        let mut map = serializer.serialize_map(None)?;
+        map.serialize_entry("code", self.code())?;
+        map.serialize_entry("message", &self.to_string())?;
+        if let AutomationError::Step { step, .. } = self {
+            map.serialize_entry("step", step)?;
+        }
+        map.end()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum RunState {
+    Running,
+    Succeeded,
+    Failed,
+    Cancelled,
+}
+
+/// What one step of a run did
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LogEntry {
+    /// Index into the script's steps
+    pub step: usize,
+    pub at: String,
+    pub message: String,
+}
+
+/// A script running (or run) on one tab, as reported to the UI
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AutomationRun {
+    pub id: String,
+    pub script_id: String,
+    pub script_name: String,
+    pub tab_id: String,
+    pub state: RunState,
+    pub log: Vec<LogEntry>,
+    /// Variables the run was started with and has captured so far;
+    /// filled in when it ends
+    pub variables: BTreeMap<String, String>,
+    pub error: Option<String>,
+    pub started_at: String,
+    pub finished_at: Option<String>,
+}
+
+struct Run {
+    info: AutomationRun,
+    /// Start order, for listing
+    seq: u64,
+    task: Option<AbortHandle>,
+}
+
+#[derive(Default)]
+struct Runs {
+    runs: HashMap<String, Run>,
+    next_seq: u64,
+}
+
+lazy_static::lazy_static! {
+    static ref RUNS: Mutex<Runs> = Mutex::new(Runs::default());
+}
+
+/// Start `script` on each of `tab_ids`, all at once. Each run gets its own
+/// copy of `variables`. The script is checked first, so a mistake in it
+/// doesn't start any run; a tab that isn't connected only fails its own.
+pub async fn start(
+    app_handle: &AppHandle,
+    script: &AutomationScript,
+    tab_ids: &[String],
+    variables: BTreeMap<String, String>,
+) -> Result<Vec<AutomationRun>, AutomationError> {
+    let program = Arc::new(Program::compile(&script.steps)?);
+    let mut started = Vec::with_capacity(tab_ids.len());
+
+    for tab_id in tab_ids {
+        let info = AutomationRun {
+            id: uuid::Uuid::new_v4().to_string(),
+            script_id: script.id.clone(),
+            script_name: script.name.clone(),
+            tab_id: tab_id.clone(),
+            state: RunState::Running,
+            log: Vec::new(),
+            variables: variables.clone(),
+            error: None,
+            started_at: Utc::now().to_rfc3339(),
+            finished_at: None,
+        };
+        {
+            let mut runs = RUNS.lock().unwrap();
+            let seq = runs.next_seq;
+            runs.next_seq += 1;
+            runs.runs.insert(info.id.clone(), Run { info: info.clone(), seq, task: None });
+        }
+        emit_status(app_handle, &info);
+
+        // Subscribed here rather than in the task, so output that arrives
+        // while the task starts up isn't missed
+        match ssh::subscribe_output(tab_id).await {
+            Ok(output) => {
+                let task = tokio::spawn(run(app_handle.clone(), program.clone(), info.clone(), output));
+                if let Some(run) = RUNS.lock().unwrap().runs.get_mut(&info.id) {
+                    run.task = Some(task.abort_handle());
+                }
+            }
+            Err(e) => finish(app_handle, &info.id, variables.clone(), Err(e.into())),
+        }
+        started.push(get(&info.id).unwrap_or(info));
+    }
+
+    Ok(started)
+}
+
+async fn run(app_handle: AppHandle, program: Arc<Program>, info: AutomationRun, mut output: broadcast::Receiver<Vec<u8>>) {
+    let mut variables = info.variables.clone();
+    let mut input = TabInput(info.tab_id.clone());
+    let result = engine::execute(&program, &mut input, &mut output, &mut variables, |step, message| {
+        record(&app_handle, &info.id, step, message)
+    })
+    .await;
+    finish(&app_handle, &info.id, variables, result);
+}
+
+/// Stop a run right away, even in the middle of a step. Variables it
+/// captured are lost; the run keeps the ones it was started with.
+pub fn cancel(app_handle: &AppHandle, id: &str) -> Result<(), AutomationError> {
+    let info = {
+        let mut runs = RUNS.lock().unwrap();
+        let run = runs.runs.get_mut(id).ok_or_else(|| AutomationError::NotFound(id.to_string()))?;
+        if run.info.state != RunState::Running {
+            return Ok(());
+        }
+        if let Some(task) = run.task.take() {
+            task.abort();
+        }
+        run.info.state = RunState::Cancelled;
+        run.info.finished_at = Some(Utc::now().to_rfc3339());
+        run.info.clone()
+    };
+    emit_status(app_handle, &info);
+    Ok(())
+}
+
+pub fn get(id: &str) -> Option<AutomationRun> {
+    RUNS.lock().unwrap().runs.get(id).map(|run| run.info.clone())
+}
+
+/// Runs on a tab, or on every tab, in the order they were started
+pub fn list(tab_id: Option<&str>) -> Vec<AutomationRun> {
+    let runs = RUNS.lock().unwrap();
+    let mut matching: Vec<&Run> = runs
+        .runs
+        .values()
+        .filter(|run| match tab_id {
+            Some(tab_id) => run.info.tab_id == tab_id,
+            None => true,
+        })
+        .collect();
+    matching.sort_by_key(|run| run.seq);
+    matching.into_iter().map(|run| run.info.clone()).collect()
+}
+
+/// Forget runs that have ended
+pub fn clear_finished(tab_id: Option<&str>) {
+    RUNS.lock().unwrap().runs.retain(|_, run| {
+        tab_id.is_some_and(|tab_id| run.info.tab_id != tab_id) || run.info.state == RunState::Running
+    });
+}
+
+/// Add a step to a run's log and tell the UI about it
+fn record(app_handle: &AppHandle, id: &str, step: usize, message: String) {
+    let entry = LogEntry { step, at: Utc::now().to_rfc3339(), message };
+    let tab_id = {
+        let mut runs = RUNS.lock().unwrap();
+        let Some(run) = runs.runs.get_mut(id) else { return };
+        run.info.log.push(entry.clone());
+        run.info.tab_id.clone()
+    };
+    let _ = app_handle.emit("automation-step", serde_json::json!({
+        "run_id": id,
+        "tab_id": tab_id,
+        "entry": entry,
+    }));
+}
+
+fn finish(
+    app_handle: &AppHandle,
+    id: &str,
+    variables: BTreeMap<String, String>,
+    result: Result<(), AutomationError>,
+) {
+    let info = {
+        let mut runs = RUNS.lock().unwrap();
+        let Some(run) = runs.runs.get_mut(id) else { return };
+        // Cancelled in the meantime
+        if run.info.state != RunState::Running {
+            return;
+        }
+        run.task = None;
+        run.info.variables = variables;
+        run.info.finished_at = Some(Utc::now().to_rfc3339());
+        match result {
+            Ok(()) => run.info.state = RunState::Succeeded,
+            Err(e) => {
+                if let AutomationError::Step { step, message } = &e {
+                    run.info.log.push(LogEntry { step: *step, at: Utc::now().to_rfc3339(), message: message.clone() });
+                }
+                run.info.state = RunState::Failed;
+                run.info.error = Some(e.to_string());
+            }
+        }
+        run.info.clone()
+    };
+    emit_status(app_handle, &info);
+}
+
+fn emit_status(app_handle: &AppHandle, info: &AutomationRun) {
+    let _ = app_handle.emit("automation-status", info);
+}
@@ -1,7 +1,9 @@
 use sqlx::{SqlitePool, Row};
+use sqlx::sqlite::SqliteRow;
+use serde::de::DeserializeOwned;
 use uuid::Uuid;
 use chrono::Utc;
-use crate::db::SSHSession;
+use crate::db::{AutomationScript, SSHSession};
 use crate::encryption::{encrypt_password, decrypt_password};
 
 #[derive(Debug, thiserror::Error)]
@@ -10,6 +12,8 @@ pub enum DbError {
     Sqlx(#[from] sqlx::Error),
     #[error("Encryption error: {0}")]
     Encryption(String),
+    #[error("Invalid stored data: {0}")]
+    Json(#[from] serde_json::Error),
     #[error("Not found")]
     NotFound,
 }
@@ -37,9 +41,51 @@ pub async fn init_db(database_url: &str) -> Result<SqlitePool, DbError> {
     .execute(&pool)
     .await?;
 
+    // Columns added after the initial schema
+    ensure_column(&pool, "sessions", "key_passphrase", "TEXT").await?;
+    ensure_column(&pool, "sessions", "agent_identity", "TEXT").await?;
+    ensure_column(&pool, "sessions", "auth_methods", "TEXT").await?;
+    ensure_column(&pool, "sessions", "jump_hosts", "TEXT").await?;
+    ensure_column(&pool, "sessions", "port_forwards", "TEXT").await?;
+    ensure_column(&pool, "sessions", "keepalive", "TEXT").await?;
+    ensure_column(&pool, "sessions", "forward_agent", "INTEGER NOT NULL DEFAULT 0").await?;
+    ensure_column(&pool, "sessions", "forward_x11", "INTEGER NOT NULL DEFAULT 0").await?;
+    ensure_column(&pool, "sessions", "env", "TEXT").await?;
+    ensure_column(&pool, "sessions", "term", "TEXT").await?;
+    ensure_column(&pool, "sessions", "terminal_modes", "TEXT").await?;
+    ensure_column(&pool, "sessions", "login_script", "TEXT").await?;
+
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS automation_scripts (
+            id TEXT PRIMARY KEY,
+            name TEXT NOT NULL,
+            description TEXT,
+            steps TEXT NOT NULL,
+            created_at TEXT NOT NULL,
+            updated_at TEXT NOT NULL
+        )"
+    )
+    .execute(&pool)
+    .await?;
+
     Ok(pool)
 }
 
+/// Add a column to an existing table if it isn't there yet
+async fn ensure_column(pool: &SqlitePool, table: &str, column: &str, definition: &str) -> Result<(), DbError> {
+    let columns = sqlx::query(&format!("PRAGMA table_info({})", table))
+        .fetch_all(pool)
+        .await?;
+
+    if !columns.iter().any(|row| row.get::<String, _>("name") == column) {
+        sqlx::query(&format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition))
+            .execute(pool)
+            .await?;
+    }
+
+    Ok(())
+}
+
 /// Get all sessions from the database
 pub async fn get_sessions(pool: &SqlitePool) -> Result<Vec<SSHSession>, DbError> {
     let rows = sqlx::query("SELECT * FROM sessions ORDER BY created_at DESC")
@@ -48,29 +94,7 @@ pub async fn get_sessions(pool: &SqlitePool) -> Result<Vec<SSHSession>, DbError>
 
     let mut sessions = Vec::new();
     for row in rows {
-        let password: Option<String> = row.get("password");
-        let decrypted_password = if let Some(enc_pass) = password {
-            if enc_pass.is_empty() {
-                None
-            } else {
-                Some(decrypt_password(&enc_pass).map_err(|e| DbError::Encryption(e.to_string()))?)
-            }
-        } else {
-            None
-        };
-
-        sessions.push(SSHSession {
-            id: row.get("id"),
-            name: row.get("name"),
-            host: row.get("host"),
-            port: row.get::<i64, _>("port") as u16,
-            username: row.get("username"),
-            auth_type: row.get("auth_type"),
-            password: decrypted_password,
-            private_key: row.get("private_key"),
-            created_at: row.get("created_at"),
-            updated_at: row.get("updated_at"),
-        });
+        sessions.push(session_from_row(&row)?);
     }
 
     Ok(sessions)
@@ -84,6 +108,11 @@ pub async fn get_session(pool: &SqlitePool, id: &str) -> Result<SSHSession, DbEr
         .await?
         .ok_or(DbError::NotFound)?;
 
+    session_from_row(&row)
+}
+
+/// Map a `sessions` row to an SSHSession, decrypting the password
+fn session_from_row(row: &SqliteRow) -> Result<SSHSession, DbError> {
     let password: Option<String> = row.get("password");
     let decrypted_password = if let Some(enc_pass) = password {
         if enc_pass.is_empty() {
@@ -104,11 +133,30 @@ pub async fn get_session(pool: &SqlitePool, id: &str) -> Result<SSHSession, DbEr
         auth_type: row.get("auth_type"),
         password: decrypted_password,
         private_key: row.get("private_key"),
+        agent_identity: row.get("agent_identity"),
+        auth_methods: json_column(row, "auth_methods")?,
+        jump_hosts: json_column(row, "jump_hosts")?,
+        port_forwards: json_column(row, "port_forwards")?,
+        keepalive: json_column(row, "keepalive")?,
+        forward_agent: row.get("forward_agent"),
+        forward_x11: row.get("forward_x11"),
+        env: json_column(row, "env")?,
+        term: row.get("term"),
+        terminal_modes: json_column(row, "terminal_modes")?,
+        login_script: json_column(row, "login_script")?,
         created_at: row.get("created_at"),
         updated_at: row.get("updated_at"),
     })
 }
 
+/// Decode a JSON TEXT column, treating NULL as the type's default
+fn json_column<T: DeserializeOwned + Default>(row: &SqliteRow, column: &str) -> Result<T, DbError> {
+    match row.get::<Option<String>, _>(column) {
+        Some(json) if !json.is_empty() => Ok(serde_json::from_str(&json)?),
+        _ => Ok(T::default()),
+    }
+}
+
 /// Save or update a session
 pub async fn save_session(pool: &SqlitePool, mut session: SSHSession) -> Result<SSHSession, DbError> {
     let now = Utc::now().to_rfc3339();
@@ -124,6 +172,21 @@ pub async fn save_session(pool: &SqlitePool, mut session: SSHSession) -> Result<
         None
     };
 
+    let auth_methods = serde_json::to_string(&session.auth_methods)?;
+    let jump_hosts = serde_json::to_string(&session.jump_hosts)?;
+
+    // Forwards are addressed by id at runtime (e.g. to stop one)
+    for forward in &mut session.port_forwards {
+        if forward.id.is_empty() {
+            forward.id = Uuid::new_v4().to_string();
+        }
+    }
+    let port_forwards = serde_json::to_string(&session.port_forwards)?;
+    let keepalive = serde_json::to_string(&session.keepalive)?;
+    let env = serde_json::to_string(&session.env)?;
+    let terminal_modes = serde_json::to_string(&session.terminal_modes)?;
+    let login_script = serde_json::to_string(&session.login_script)?;
+
     if session.id.is_empty() {
         // Create new session
         session.id = Uuid::new_v4().to_string();
@@ -131,8 +194,8 @@ pub async fn save_session(pool: &SqlitePool, mut session: SSHSession) -> Result<
         session.updated_at = now;
 
         sqlx::query(
-            "INSERT INTO sessions (id, name, host, port, username, auth_type, password, private_key, created_at, updated_at)
-             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)"
+            "INSERT INTO sessions (id, name, host, port, username, auth_type, password, private_key, agent_identity, auth_methods, jump_hosts, port_forwards, keepalive, forward_agent, forward_x11, env, term, terminal_modes, login_script, created_at, updated_at)
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)"
         )
         .bind(&session.id)
         .bind(&session.name)
@@ -142,6 +205,17 @@ pub async fn save_session(pool: &SqlitePool, mut session: SSHSession) -> Result<
         .bind(&session.auth_type)
         .bind(&encrypted_password)
         .bind(&session.private_key)
+        .bind(&session.agent_identity)
+        .bind(&auth_methods)
+        .bind(&jump_hosts)
+        .bind(&port_forwards)
+        .bind(&keepalive)
+        .bind(session.forward_agent)
+        .bind(session.forward_x11)
+        .bind(&env)
+        .bind(&session.term)
+        .bind(&terminal_modes)
+        .bind(&login_script)
         .bind(&session.created_at)
         .bind(&session.updated_at)
         .execute(pool)
@@ -152,7 +226,7 @@ pub async fn save_session(pool: &SqlitePool, mut session: SSHSession) -> Result<
 
         sqlx::query(
             "UPDATE sessions SET name = ?, host = ?, port = ?, username = ?, auth_type = ?,
-             password = ?, private_key = ?, updated_at = ?
+             password = ?, private_key = ?, agent_identity = ?, auth_methods = ?, jump_hosts = ?, port_forwards = ?, keepalive = ?, forward_agent = ?, forward_x11 = ?, env = ?, term = ?, terminal_modes = ?, login_script = ?, updated_at = ?
              WHERE id = ?"
         )
         .bind(&session.name)
@@ -162,6 +236,17 @@ pub async fn save_session(pool: &SqlitePool, mut session: SSHSession) -> Result<
         .bind(&session.auth_type)
         .bind(&encrypted_password)
         .bind(&session.private_key)
+        .bind(&session.agent_identity)
+        .bind(&auth_methods)
+        .bind(&jump_hosts)
+        .bind(&port_forwards)
+        .bind(&keepalive)
+        .bind(session.forward_agent)
+        .bind(session.forward_x11)
+        .bind(&env)
+        .bind(&session.term)
+        .bind(&terminal_modes)
+        .bind(&login_script)
         .bind(&session.updated_at)
         .bind(&session.id)
         .execute(pool)
@@ -172,6 +257,38 @@ pub async fn save_session(pool: &SqlitePool, mut session: SSHSession) -> Result<
     Ok(session)
 }
 
+/// Get the remembered private key passphrase for a session, if any
+pub async fn get_key_passphrase(pool: &SqlitePool, session_id: &str) -> Result<Option<String>, DbError> {
+    let passphrase = sqlx::query_scalar::<_, Option<String>>("SELECT key_passphrase FROM sessions WHERE id = ?")
+        .bind(session_id)
+        .fetch_optional(pool)
+        .await?
+        .flatten();
+
+    match passphrase {
+        Some(enc) if !enc.is_empty() => Ok(Some(
+            decrypt_password(&enc).map_err(|e| DbError::Encryption(e.to_string()))?,
+        )),
+        _ => Ok(None),
+    }
+}
+
+/// Remember (or forget, with None) the private key passphrase for a session
+pub async fn set_key_passphrase(pool: &SqlitePool, session_id: &str, passphrase: Option<&str>) -> Result<(), DbError> {
+    let encrypted = match passphrase {
+        Some(passphrase) => Some(encrypt_password(passphrase).map_err(|e| DbError::Encryption(e.to_string()))?),
+        None => None,
+    };
+
+    sqlx::query("UPDATE sessions SET key_passphrase = ? WHERE id = ?")
+        .bind(&encrypted)
+        .bind(session_id)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
 /// Delete a session by ID
 pub async fn delete_session(pool: &SqlitePool, id: &str) -> Result<(), DbError> {
     sqlx::query("DELETE FROM sessions WHERE id = ?")
@@ -181,3 +298,87 @@ pub async fn delete_session(pool: &SqlitePool, id: &str) -> Result<(), DbError>
 
     Ok(())
 }
+
+/// Get all automation scripts, by name
+pub async fn get_automation_scripts(pool: &SqlitePool) -> Result<Vec<AutomationScript>, DbError> {
+    let rows = sqlx::query("SELECT * FROM automation_scripts ORDER BY name")
+        .fetch_all(pool)
+        .await?;
+
+    let mut scripts = Vec::new();
+    for row in rows {
+        scripts.push(automation_script_from_row(&row)?);
+    }
+
+    Ok(scripts)
+}
+
+/// Get a single automation script by ID
+pub async fn get_automation_script(pool: &SqlitePool, id: &str) -> Result<AutomationScript, DbError> {
+    let row = sqlx::query("SELECT * FROM automation_scripts WHERE id = ?")
+        .bind(id)
+        .fetch_optional(pool)
+        .await?
+        .ok_or(DbError::NotFound)?;
+
+    automation_script_from_row(&row)
+}
+
+fn automation_script_from_row(row: &SqliteRow) -> Result<AutomationScript, DbError> {
+    Ok(AutomationScript {
+        id: row.get("id"),
+        name: row.get("name"),
+        description: row.get("description"),
+        steps: json_column(row, "steps")?,
+        created_at: row.get("created_at"),
+        updated_at: row.get("updated_at"),
+    })
+}
+
+/// Save or update an automation script
+pub async fn save_automation_script(pool: &SqlitePool, mut script: AutomationScript) -> Result<AutomationScript, DbError> {
+    let now = Utc::now().to_rfc3339();
+    let steps = serde_json::to_string(&script.steps)?;
+
+    if script.id.is_empty() {
+        script.id = Uuid::new_v4().to_string();
+        script.created_at = now.clone();
+        script.updated_at = now;
+
+        sqlx::query(
+            "INSERT INTO automation_scripts (id, name, description, steps, created_at, updated_at)
+             VALUES (?, ?, ?, ?, ?, ?)"
+        )
+        .bind(&script.id)
+        .bind(&script.name)
+        .bind(&script.description)
+        .bind(&steps)
+        .bind(&script.created_at)
+        .bind(&script.updated_at)
+        .execute(pool)
+        .await?;
+    } else {
+        script.updated_at = now;
+
+        sqlx::query("UPDATE automation_scripts SET name = ?, description = ?, steps = ?, updated_at = ? WHERE id = ?")
+            .bind(&script.name)
+            .bind(&script.description)
+            .bind(&steps)
+            .bind(&script.updated_at)
+            .bind(&script.id)
+            .execute(pool)
+            .await?;
+    }
+
+    Ok(script)
+}
+
+/// Delete an automation script by ID
+pub async fn delete_automation_script(pool: &SqlitePool, id: &str) -> Result<(), DbError> {
+    sqlx::query("DELETE FROM automation_scripts WHERE id = ?")
+        .bind(id)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
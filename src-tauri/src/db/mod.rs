@@ -1,8 +1,195 @@
+use std::collections::BTreeMap;
 use serde::{Deserialize, Serialize};
 use tauri_plugin_sql::{Migration, MigrationKind};
 
 pub mod async_db;
 
+/// An SSH user authentication method
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AuthMethod {
+    Password,
+    #[serde(rename = "publickey")]
+    PublicKey,
+    Agent,
+    KeyboardInteractive,
+}
+
+impl AuthMethod {
+    /// Method name as used in the SSH protocol
+    pub fn name(&self) -> &'static str {
+        match self {
+            AuthMethod::Password => "password",
+            AuthMethod::PublicKey => "publickey",
+            AuthMethod::Agent => "agent",
+            AuthMethod::KeyboardInteractive => "keyboard-interactive",
+        }
+    }
+}
+
+/// Which way a port forward carries connections
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ForwardKind {
+    /// `-L`: listen on `bind_address:bind_port` here, connect from the
+    /// server to `target_host:target_port`
+    #[default]
+    Local,
+    /// `-R`: the server listens on `bind_address:bind_port`, and we connect
+    /// to `target_host:target_port` from this machine
+    Remote,
+    /// `-D`: a SOCKS proxy on `bind_address:bind_port`; each client picks
+    /// its own target, so `target_host`/`target_port` are unused
+    Dynamic,
+}
+
+/// A port forward saved with a session
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PortForward {
+    #[serde(default)]
+    pub id: String,
+    #[serde(default)]
+    pub kind: ForwardKind,
+    #[serde(default = "default_bind_address")]
+    pub bind_address: String,
+    pub bind_port: u16,
+    /// Saved as `remoteHost`/`remotePort` before remote forwards existed
+    #[serde(default, alias = "remoteHost")]
+    pub target_host: String,
+    #[serde(default, alias = "remotePort")]
+    pub target_port: u16,
+    /// Start the forward as soon as the session connects
+    #[serde(default = "default_true")]
+    pub auto_start: bool,
+}
+
+/// How a session notices and recovers from a dead connection
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct KeepaliveSettings {
+    /// Seconds of silence before sending a keepalive; 0 turns them off
+    pub interval_secs: u32,
+    /// Unanswered keepalives before the connection is considered dead
+    pub max_missed: u32,
+    /// Reconnect and reopen the shell when the connection is lost
+    pub auto_reconnect: bool,
+    /// Reconnect attempts before giving up
+    pub max_reconnect_attempts: u32,
+}
+
+impl Default for KeepaliveSettings {
+    fn default() -> Self {
+        Self {
+            interval_secs: 30,
+            max_missed: 3,
+            auto_reconnect: false,
+            max_reconnect_attempts: 10,
+        }
+    }
+}
+
+/// A PTY terminal mode sent with the pty request, e.g. `VERASE` = 127
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TerminalMode {
+    /// Opcode name from RFC 4254 section 8, e.g. "ECHO" or "VINTR"
+    pub name: String,
+    pub value: u32,
+}
+
+/// A step of the script run right after login
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum LoginStep {
+    /// Type a command and press Enter
+    #[serde(rename_all = "camelCase")]
+    Command { command: String },
+    /// Wait until the output matches `pattern` (a regex), then type `send`
+    /// and press Enter. An empty `send` only waits.
+    #[serde(rename_all = "camelCase")]
+    Expect {
+        pattern: String,
+        #[serde(default)]
+        send: String,
+        /// How long to wait before giving up on the rest of the script
+        #[serde(default)]
+        timeout_ms: Option<u64>,
+    },
+}
+
+/// A saved script that drives open tabs, see `crate::automation`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AutomationScript {
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub steps: Vec<AutomationStep>,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+/// A step of an automation script. `${name}` in `Send` text and `Fail`
+/// messages is replaced with the variable of that name.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum AutomationStep {
+    /// Type `text`, followed by Enter unless `enter` is off
+    #[serde(rename_all = "camelCase")]
+    Send {
+        text: String,
+        #[serde(default = "default_true")]
+        enter: bool,
+    },
+    /// Wait until the output matches one of `branches`, and take the one
+    /// that matches first
+    #[serde(rename_all = "camelCase")]
+    Expect {
+        branches: Vec<ExpectBranch>,
+        #[serde(default)]
+        timeout_ms: Option<u64>,
+        /// Label to go to on timeout; without one the run fails
+        #[serde(default)]
+        on_timeout: Option<String>,
+    },
+    /// A place `goto`s can jump to
+    #[serde(rename_all = "camelCase")]
+    Label { name: String },
+    #[serde(rename_all = "camelCase")]
+    Goto { label: String },
+    #[serde(rename_all = "camelCase")]
+    Sleep { ms: u64 },
+    /// Stop the run and mark it failed
+    #[serde(rename_all = "camelCase")]
+    Fail { message: String },
+}
+
+/// One outcome of an `Expect` step
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExpectBranch {
+    /// Regex matched against the output, without terminal escapes
+    pub pattern: String,
+    /// Variable to store the whole match in. Named groups like
+    /// `(?P<version>\S+)` are stored under their own names as well.
+    #[serde(default)]
+    pub capture: Option<String>,
+    /// Label to continue at; the next step if unset
+    #[serde(default)]
+    pub goto: Option<String>,
+}
+
+fn default_bind_address() -> String {
+    "127.0.0.1".to_string()
+}
+
+fn default_true() -> bool {
+    true
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct SSHSession {
@@ -14,10 +201,58 @@ pub struct SSHSession {
     pub auth_type: String,
     pub password: Option<String>,
     pub private_key: Option<String>,
+    /// For `agent` auth: only offer the agent identity with this fingerprint or comment
+    #[serde(default)]
+    pub agent_identity: Option<String>,
+    /// Methods tried in order until one succeeds, e.g. publickey then
+    /// keyboard-interactive for servers that require both. Empty means
+    /// just the method implied by `auth_type`.
+    #[serde(default)]
+    pub auth_methods: Vec<AuthMethod>,
+    /// Saved session ids to hop through, in order, before reaching this host
+    #[serde(default)]
+    pub jump_hosts: Vec<String>,
+    #[serde(default)]
+    pub port_forwards: Vec<PortForward>,
+    #[serde(default)]
+    pub keepalive: KeepaliveSettings,
+    /// Let the server use our ssh-agent (`ssh -A`). Anyone with root there
+    /// can use the keys while we're connected, so it's off unless asked for.
+    #[serde(default)]
+    pub forward_agent: bool,
+    /// Let remote GUI programs open windows on our `$DISPLAY` (`ssh -X`)
+    #[serde(default)]
+    pub forward_x11: bool,
+    /// Variables sent with `env` requests before the shell starts, e.g.
+    /// LANG=bg_BG.UTF-8. The server drops any its `AcceptEnv` doesn't list.
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    /// TERM for the pty request; None means xterm-256color
+    #[serde(default)]
+    pub term: Option<String>,
+    #[serde(default)]
+    pub terminal_modes: Vec<TerminalMode>,
+    /// Run in the shell after every login, e.g. `sudo -i`, `cd /srv/app`
+    #[serde(default)]
+    pub login_script: Vec<LoginStep>,
     pub created_at: String,
     pub updated_at: String,
 }
 
+impl SSHSession {
+    pub fn auth_chain(&self) -> Vec<AuthMethod> {
+        if !self.auth_methods.is_empty() {
+            return self.auth_methods.clone();
+        }
+        vec![match self.auth_type.as_str() {
+            "password" => AuthMethod::Password,
+            "agent" => AuthMethod::Agent,
+            "keyboard-interactive" => AuthMethod::KeyboardInteractive,
+            _ => AuthMethod::PublicKey,
+        }]
+    }
+}
+
 // Legacy migration function - no longer used with sqlx
 #[allow(dead_code)]
 pub fn get_migrations() -> Vec<Migration> {
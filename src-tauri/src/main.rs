@@ -1,40 +1,56 @@
 // Prevents additional console window on Windows in release
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
+mod automation;
 mod db;
 mod ssh;
+mod sftp;
 mod encryption;
 
-use db::SSHSession;
+use automation::{AutomationError, AutomationRun};
+use db::{AutomationScript, PortForward, SSHSession};
 use db::async_db;
+use ssh::{PtySize, SSHError, TabExit};
+use ssh::output::{OutputMode, TerminalOutput};
+use ssh::agent::AgentIdentity;
+use ssh::exec::ExecResult;
+use ssh::forward::ForwardStatus;
+use ssh::known_hosts::{self, KnownHostEntry};
+use sftp::{SftpEntry, SftpError};
+use sftp::sync::{SyncPlan, SyncRequest};
+use sftp::transfer::{self, TransferInfo, TransferRequest};
+use base64::{Engine as _, engine::general_purpose};
 use sqlx::SqlitePool;
+use std::collections::BTreeMap;
+use std::time::Duration;
+use tauri::ipc::Channel;
 use tauri::{AppHandle, Manager, State};
 
 // New async-friendly state using SQLx connection pool
 pub struct DbState(pub SqlitePool);
 
 #[tauri::command]
-async fn get_sessions(db_state: State<'_, DbState>) -> Result<Vec<SSHSession>, String> {
+async fn get_sessions(db_state: State<'_, DbState>) -> Result<Vec<SSHSession>, SSHError> {
     async_db::get_sessions(&db_state.0)
         .await
-        .map_err(|e| e.to_string())
+        .map_err(SSHError::from)
 }
 
 #[tauri::command]
 async fn save_session(
     db_state: State<'_, DbState>,
     session: SSHSession,
-) -> Result<SSHSession, String> {
+) -> Result<SSHSession, SSHError> {
     async_db::save_session(&db_state.0, session)
         .await
-        .map_err(|e| e.to_string())
+        .map_err(SSHError::from)
 }
 
 #[tauri::command]
-async fn delete_session(db_state: State<'_, DbState>, session_id: String) -> Result<(), String> {
+async fn delete_session(db_state: State<'_, DbState>, session_id: String) -> Result<(), SSHError> {
     async_db::delete_session(&db_state.0, &session_id)
         .await
-        .map_err(|e| e.to_string())
+        .map_err(SSHError::from)
 }
 
 #[tauri::command]
@@ -43,29 +59,250 @@ async fn connect_ssh(
     app_handle: AppHandle,
     tab_id: String,
     session_id: String,
-) -> Result<(), String> {
+    size: Option<PtySize>,
+    output_mode: Option<OutputMode>,
+    on_output: Channel<serde_json::Value>,
+) -> Result<(), SSHError> {
     println!("Connecting SSH for tab {} with session {}", tab_id, session_id);
+    let size = size.unwrap_or_default();
 
     // Use async database call - no blocking!
     let session = async_db::get_session(&db_state.0, &session_id)
         .await
-        .map_err(|e| e.to_string())?;
+        .map_err(SSHError::from)?;
 
     println!("Found session: {}@{}:{}", session.username, session.host, session.port);
 
-    // Spawn a tokio task for the long-running SSH connection
+    let output = TerminalOutput::new(on_output, output_mode.unwrap_or_default());
+    let pool = db_state.0.clone();
+
+    // Spawn a tokio task for the long-running SSH connection. How it ends,
+    // error code included, reaches the UI as the tab's `failed` or `closed`
+    // lifecycle event, so the result needs no handling here.
     tokio::spawn(async move {
-        if let Err(e) = ssh::connect(&session, app_handle, tab_id).await {
-            eprintln!("SSH connection task failed: {}", e);
-        }
+        let _ = ssh::connect(pool, &session, app_handle, tab_id, size, output).await;
     });
 
     Ok(())
 }
 
 #[tauri::command]
-async fn send_terminal_input(tab_id: String, data: String) -> Result<(), String> {
-    ssh::send_input(&tab_id, &data).await.map_err(|e| e.to_string())
+async fn send_terminal_input(tab_id: String, data: String) -> Result<(), SSHError> {
+    ssh::send_input(&tab_id, &data).await
+}
+
+#[tauri::command]
+async fn resize_terminal(tab_id: String, cols: u32, rows: u32, pixel_w: u32, pixel_h: u32) -> Result<(), SSHError> {
+    ssh::resize(&tab_id, PtySize { cols, rows, pixel_w, pixel_h }).await
+}
+
+#[tauri::command]
+async fn set_output_mode(tab_id: String, mode: OutputMode) -> Result<(), SSHError> {
+    ssh::set_output_mode(&tab_id, mode).await
+}
+
+#[tauri::command]
+async fn ack_terminal_output(tab_id: String, bytes: usize) -> Result<(), SSHError> {
+    ssh::ack_output(&tab_id, bytes).await
+}
+
+#[tauri::command]
+async fn send_signal(tab_id: String, signal: String) -> Result<(), SSHError> {
+    ssh::send_signal(&tab_id, &signal).await
+}
+
+#[tauri::command]
+async fn get_exit_status(tab_id: String) -> Result<Option<u32>, SSHError> {
+    ssh::exit_status(&tab_id).await
+}
+
+#[tauri::command]
+async fn get_tab_exit(tab_id: String) -> Result<Option<TabExit>, String> {
+    Ok(ssh::tab_exit(&tab_id).await)
+}
+
+#[tauri::command]
+async fn start_port_forward(tab_id: String, forward: PortForward) -> Result<ForwardStatus, SSHError> {
+    ssh::start_forward(&tab_id, forward).await
+}
+
+#[tauri::command]
+async fn stop_port_forward(tab_id: String, forward_id: String) -> Result<bool, SSHError> {
+    ssh::stop_forward(&tab_id, &forward_id).await
+}
+
+#[tauri::command]
+async fn list_port_forwards(tab_id: String) -> Result<Vec<ForwardStatus>, SSHError> {
+    ssh::list_forwards(&tab_id).await
+}
+
+#[tauri::command]
+async fn sftp_realpath(tab_id: String, path: String) -> Result<String, SftpError> {
+    sftp::realpath(&tab_id, &path).await
+}
+
+#[tauri::command]
+async fn sftp_list_dir(tab_id: String, path: String) -> Result<Vec<SftpEntry>, SftpError> {
+    sftp::list_dir(&tab_id, &path).await
+}
+
+#[tauri::command]
+async fn sftp_stat(tab_id: String, path: String) -> Result<SftpEntry, SftpError> {
+    sftp::stat(&tab_id, &path).await
+}
+
+#[tauri::command]
+async fn sftp_rename(tab_id: String, from: String, to: String) -> Result<(), SftpError> {
+    sftp::rename(&tab_id, &from, &to).await
+}
+
+#[tauri::command]
+async fn sftp_delete(tab_id: String, path: String) -> Result<(), SftpError> {
+    sftp::delete(&tab_id, &path).await
+}
+
+#[tauri::command]
+async fn sftp_mkdir(tab_id: String, path: String) -> Result<(), SftpError> {
+    sftp::mkdir(&tab_id, &path).await
+}
+
+#[tauri::command]
+async fn sftp_chmod(tab_id: String, path: String, mode: u32) -> Result<(), SftpError> {
+    sftp::chmod(&tab_id, &path, mode).await
+}
+
+/// File contents as base64
+#[tauri::command]
+async fn sftp_read_file(tab_id: String, path: String) -> Result<String, SftpError> {
+    let data = sftp::read_file(&tab_id, &path).await?;
+    Ok(general_purpose::STANDARD.encode(data))
+}
+
+/// `data` is base64
+#[tauri::command]
+async fn sftp_write_file(tab_id: String, path: String, data: String) -> Result<(), SftpError> {
+    let data = general_purpose::STANDARD
+        .decode(data)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+    sftp::write_file(&tab_id, &path, &data).await
+}
+
+#[tauri::command]
+async fn queue_transfer(app_handle: AppHandle, tab_id: String, request: TransferRequest) -> Result<TransferInfo, String> {
+    Ok(transfer::enqueue(&app_handle, &tab_id, request))
+}
+
+#[tauri::command]
+async fn pause_transfer(app_handle: AppHandle, transfer_id: String) -> Result<(), SftpError> {
+    transfer::pause(&app_handle, &transfer_id)
+}
+
+#[tauri::command]
+async fn resume_transfer(app_handle: AppHandle, transfer_id: String) -> Result<(), SftpError> {
+    transfer::resume(&app_handle, &transfer_id)
+}
+
+#[tauri::command]
+async fn cancel_transfer(app_handle: AppHandle, transfer_id: String) -> Result<(), SftpError> {
+    transfer::cancel(&app_handle, &transfer_id)
+}
+
+#[tauri::command]
+async fn list_transfers(tab_id: Option<String>) -> Result<Vec<TransferInfo>, String> {
+    Ok(transfer::list(tab_id.as_deref()))
+}
+
+#[tauri::command]
+async fn clear_finished_transfers(tab_id: Option<String>) -> Result<(), String> {
+    transfer::clear_finished(tab_id.as_deref());
+    Ok(())
+}
+
+/// Dry run: what `run_sync` would do
+#[tauri::command]
+async fn plan_sync(tab_id: String, request: SyncRequest) -> Result<SyncPlan, SftpError> {
+    sftp::sync::plan(&tab_id, &request).await
+}
+
+/// Carry out the plan `plan_sync` returned for the same request
+#[tauri::command]
+async fn run_sync(app_handle: AppHandle, tab_id: String, request: SyncRequest, plan: SyncPlan) -> Result<SyncPlan, SftpError> {
+    sftp::sync::run(&app_handle, &tab_id, &request, plan).await
+}
+
+#[tauri::command]
+async fn get_automation_scripts(db_state: State<'_, DbState>) -> Result<Vec<AutomationScript>, SSHError> {
+    async_db::get_automation_scripts(&db_state.0)
+        .await
+        .map_err(SSHError::from)
+}
+
+#[tauri::command]
+async fn save_automation_script(
+    db_state: State<'_, DbState>,
+    script: AutomationScript,
+) -> Result<AutomationScript, SSHError> {
+    async_db::save_automation_script(&db_state.0, script)
+        .await
+        .map_err(SSHError::from)
+}
+
+#[tauri::command]
+async fn delete_automation_script(db_state: State<'_, DbState>, script_id: String) -> Result<(), SSHError> {
+    async_db::delete_automation_script(&db_state.0, &script_id)
+        .await
+        .map_err(SSHError::from)
+}
+
+/// Start a saved script on each of the tabs; progress arrives as
+/// `automation-step` and `automation-status` events
+#[tauri::command]
+async fn run_automation(
+    db_state: State<'_, DbState>,
+    app_handle: AppHandle,
+    script_id: String,
+    tab_ids: Vec<String>,
+    variables: Option<BTreeMap<String, String>>,
+) -> Result<Vec<AutomationRun>, AutomationError> {
+    let script = async_db::get_automation_script(&db_state.0, &script_id)
+        .await
+        .map_err(SSHError::from)?;
+    automation::start(&app_handle, &script, &tab_ids, variables.unwrap_or_default()).await
+}
+
+#[tauri::command]
+async fn cancel_automation_run(app_handle: AppHandle, run_id: String) -> Result<(), AutomationError> {
+    automation::cancel(&app_handle, &run_id)
+}
+
+#[tauri::command]
+async fn get_automation_run(run_id: String) -> Result<Option<AutomationRun>, String> {
+    Ok(automation::get(&run_id))
+}
+
+#[tauri::command]
+async fn list_automation_runs(tab_id: Option<String>) -> Result<Vec<AutomationRun>, String> {
+    Ok(automation::list(tab_id.as_deref()))
+}
+
+#[tauri::command]
+async fn clear_finished_automation_runs(tab_id: Option<String>) -> Result<(), String> {
+    automation::clear_finished(tab_id.as_deref());
+    Ok(())
+}
+
+#[tauri::command]
+async fn exec_command(
+    db_state: State<'_, DbState>,
+    app_handle: AppHandle,
+    session_id: String,
+    command: String,
+    timeout_ms: Option<u64>,
+    tab_id: Option<String>,
+) -> Result<ExecResult, SSHError> {
+    let timeout = timeout_ms.map(Duration::from_millis).unwrap_or(ssh::exec::DEFAULT_TIMEOUT);
+    ssh::exec::exec_command(&db_state.0, &app_handle, &session_id, &command, timeout, tab_id.as_deref())
+        .await
 }
 
 #[tauri::command]
@@ -74,6 +311,48 @@ async fn close_terminal(tab_id: String) -> Result<(), String> {
     Ok(())
 }
 
+#[tauri::command]
+async fn submit_key_passphrase(prompt_id: String, passphrase: Option<String>, remember: Option<bool>) -> Result<(), SSHError> {
+    let answer = serde_json::json!({
+        "passphrase": passphrase,
+        "remember": remember.unwrap_or(false),
+    });
+    ssh::prompt::answer(&prompt_id, answer).await
+}
+
+#[tauri::command]
+async fn answer_keyboard_interactive(prompt_id: String, responses: Option<Vec<String>>) -> Result<(), SSHError> {
+    ssh::prompt::answer(&prompt_id, serde_json::json!({ "responses": responses })).await
+}
+
+#[tauri::command]
+async fn forget_key_passphrase(db_state: State<'_, DbState>, session_id: String) -> Result<(), SSHError> {
+    async_db::set_key_passphrase(&db_state.0, &session_id, None)
+        .await
+        .map_err(SSHError::from)
+}
+
+#[tauri::command]
+async fn list_agent_identities() -> Result<Vec<AgentIdentity>, SSHError> {
+    ssh::agent::list_identities().await
+}
+
+#[tauri::command]
+async fn list_known_hosts() -> Result<Vec<KnownHostEntry>, SSHError> {
+    known_hosts::list(&known_hosts::default_path()).map_err(SSHError::from)
+}
+
+#[tauri::command]
+async fn accept_host_key(prompt_id: String, accept: bool) -> Result<(), SSHError> {
+    ssh::prompt::answer(&prompt_id, serde_json::Value::Bool(accept)).await
+}
+
+#[tauri::command]
+async fn revoke_host_key(host: String, port: u16, fingerprint: Option<String>) -> Result<usize, SSHError> {
+    known_hosts::revoke(&known_hosts::default_path(), &host, port, fingerprint.as_deref())
+        .map_err(SSHError::from)
+}
+
 fn main() {
     tauri::Builder::default()
         .setup(|app| {
@@ -103,7 +382,49 @@ fn main() {
             delete_session,
             connect_ssh,
             send_terminal_input,
+            resize_terminal,
+            set_output_mode,
+            ack_terminal_output,
+            send_signal,
+            get_exit_status,
+            get_tab_exit,
             close_terminal,
+            start_port_forward,
+            stop_port_forward,
+            list_port_forwards,
+            sftp_realpath,
+            sftp_list_dir,
+            sftp_stat,
+            sftp_rename,
+            sftp_delete,
+            sftp_mkdir,
+            sftp_chmod,
+            sftp_read_file,
+            sftp_write_file,
+            queue_transfer,
+            pause_transfer,
+            resume_transfer,
+            cancel_transfer,
+            list_transfers,
+            clear_finished_transfers,
+            plan_sync,
+            run_sync,
+            get_automation_scripts,
+            save_automation_script,
+            delete_automation_script,
+            run_automation,
+            cancel_automation_run,
+            get_automation_run,
+            list_automation_runs,
+            clear_finished_automation_runs,
+            exec_command,
+            submit_key_passphrase,
+            forget_key_passphrase,
+            answer_keyboard_interactive,
+            list_agent_identities,
+            list_known_hosts,
+            accept_host_key,
+            revoke_host_key,
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
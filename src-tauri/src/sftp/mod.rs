@@ -0,0 +1,303 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Weak};
+use russh::client;
+use russh_sftp::client::SftpSession;
+use russh_sftp::protocol::FileAttributes;
+use serde::Serialize;
+use tokio::sync::RwLock;
+use crate::ssh::{self, Client, SSHError};
+
+pub mod sync;
+pub mod transfer;
+
+#[derive(Debug, thiserror::Error)]
+pub enum SftpError {
+    #[error(transparent)]
+    Ssh(#[from] SSHError),
+    #[error("SFTP error: {0}")]
+    Sftp(#[from] russh_sftp::client::error::Error),
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Transfer failed: {0}")]
+    Transfer(String),
+}
+
+impl SftpError {
+    /// Stable identifier for the UI, like `SSHError::code`
+    pub fn code(&self) -> &'static str {
+        match self {
+            SftpError::Ssh(e) => e.code(),
+            SftpError::Sftp(_) => "sftp",
+            SftpError::Io(e) => ssh::io_code(e),
+            SftpError::Transfer(_) => "transfer",
+        }
+    }
+}
+
+/// `{ code, message }`; SSH errors keep their own shape
+impl Serialize for SftpError {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeMap;
+
+        if let SftpError::Ssh(e) = self {
+            return e.serialize(serializer);
+        }
+        let mut map = serializer.serialize_map(Some(2))?;
+        map.serialize_entry("code", self.code())?;
+        map.serialize_entry("message", &self.to_string())?;
+        map.end()
+    }
+}
+
+impl From<russh::Error> for SftpError {
+    fn from(e: russh::Error) -> Self {
+        SftpError::Ssh(SSHError::Russh(e))
+    }
+}
+
+/// File type bits of `st_mode`
+const S_IFMT: u32 = 0o170000;
+const S_IFREG: u32 = 0o100000;
+const S_IFDIR: u32 = 0o040000;
+const S_IFLNK: u32 = 0o120000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum EntryKind {
+    File,
+    Directory,
+    Symlink,
+    Other,
+}
+
+impl EntryKind {
+    fn from_mode(mode: u32) -> Self {
+        match mode & S_IFMT {
+            S_IFREG => EntryKind::File,
+            S_IFDIR => EntryKind::Directory,
+            S_IFLNK => EntryKind::Symlink,
+            _ => EntryKind::Other,
+        }
+    }
+}
+
+/// A remote file or directory, as shown in the file browser
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SftpEntry {
+    pub name: String,
+    pub path: String,
+    pub kind: EntryKind,
+    pub size: u64,
+    /// Permission bits without the file type, e.g. 0o755
+    pub mode: u32,
+    /// Seconds since the Unix epoch
+    pub mtime: Option<u32>,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    /// Owner and group names, when the server sends them
+    pub owner: Option<String>,
+    pub group: Option<String>,
+    pub symlink_target: Option<String>,
+}
+
+impl SftpEntry {
+    fn new(name: String, path: String, attrs: &FileAttributes) -> Self {
+        let mode = attrs.permissions.unwrap_or(0);
+        Self {
+            name,
+            path,
+            kind: EntryKind::from_mode(mode),
+            size: attrs.size.unwrap_or(0),
+            mode: mode & !S_IFMT,
+            mtime: attrs.mtime,
+            uid: attrs.uid,
+            gid: attrs.gid,
+            owner: attrs.user.clone(),
+            group: attrs.group.clone(),
+            symlink_target: None,
+        }
+    }
+}
+
+struct CachedSftp {
+    /// The connection the subsystem was opened on. Weak, so a closed tab
+    /// doesn't keep it alive; a reconnect gives the tab a new one.
+    handle: Weak<client::Handle<Client>>,
+    sftp: Arc<SftpSession>,
+}
+
+lazy_static::lazy_static! {
+    static ref SFTP_SESSIONS: RwLock<HashMap<String, CachedSftp>> = RwLock::new(HashMap::new());
+}
+
+/// Drop the tab's SFTP session once the tab is gone
+pub async fn forget(tab_id: &str) {
+    SFTP_SESSIONS.write().await.remove(tab_id);
+}
+
+/// The tab's SFTP session, opened on its live connection the first time
+/// it is needed
+pub async fn session(tab_id: &str) -> Result<Arc<SftpSession>, SftpError> {
+    let handle = match ssh::connection_handle(tab_id).await {
+        Ok(handle) => handle,
+        Err(e) => {
+            SFTP_SESSIONS.write().await.remove(tab_id);
+            return Err(e.into());
+        }
+    };
+
+    if let Some(cached) = SFTP_SESSIONS.read().await.get(tab_id) {
+        if Weak::ptr_eq(&cached.handle, &Arc::downgrade(&handle)) {
+            return Ok(cached.sftp.clone());
+        }
+    }
+
+    let channel = handle.channel_open_session().await?;
+    channel.request_subsystem(true, "sftp").await?;
+    let sftp = Arc::new(SftpSession::new(channel.into_stream()).await?);
+
+    SFTP_SESSIONS.write().await.insert(tab_id.to_string(), CachedSftp {
+        handle: Arc::downgrade(&handle),
+        sftp: sftp.clone(),
+    });
+    Ok(sftp)
+}
+
+/// Resolve a path such as `.` (the login directory) to an absolute one
+pub async fn realpath(tab_id: &str, path: &str) -> Result<String, SftpError> {
+    Ok(session(tab_id).await?.canonicalize(path).await?)
+}
+
+/// List a directory, without `.` and `..`, directories first
+pub async fn list_dir(tab_id: &str, path: &str) -> Result<Vec<SftpEntry>, SftpError> {
+    let sftp = session(tab_id).await?;
+
+    let mut entries = Vec::new();
+    for dir_entry in sftp.read_dir(path).await? {
+        let name = dir_entry.file_name();
+        if name == "." || name == ".." {
+            continue;
+        }
+        let entry_path = join(path, &name);
+        let mut entry = SftpEntry::new(name, entry_path, &dir_entry.metadata());
+        if entry.kind == EntryKind::Symlink {
+            entry.symlink_target = sftp.read_link(&entry.path).await.ok();
+        }
+        entries.push(entry);
+    }
+
+    entries.sort_by(|a, b| {
+        (b.kind == EntryKind::Directory)
+            .cmp(&(a.kind == EntryKind::Directory))
+            .then_with(|| a.name.cmp(&b.name))
+    });
+    Ok(entries)
+}
+
+/// Stat a path without following a final symlink
+pub async fn stat(tab_id: &str, path: &str) -> Result<SftpEntry, SftpError> {
+    let sftp = session(tab_id).await?;
+    let attrs = sftp.symlink_metadata(path).await?;
+
+    let mut entry = SftpEntry::new(file_name(path).to_string(), path.to_string(), &attrs);
+    if entry.kind == EntryKind::Symlink {
+        entry.symlink_target = sftp.read_link(path).await.ok();
+    }
+    Ok(entry)
+}
+
+pub async fn rename(tab_id: &str, from: &str, to: &str) -> Result<(), SftpError> {
+    Ok(session(tab_id).await?.rename(from, to).await?)
+}
+
+/// Delete a file, symlink or empty directory
+pub async fn delete(tab_id: &str, path: &str) -> Result<(), SftpError> {
+    let sftp = session(tab_id).await?;
+    let attrs = sftp.symlink_metadata(path).await?;
+
+    if EntryKind::from_mode(attrs.permissions.unwrap_or(0)) == EntryKind::Directory {
+        sftp.remove_dir(path).await?;
+    } else {
+        sftp.remove_file(path).await?;
+    }
+    Ok(())
+}
+
+pub async fn mkdir(tab_id: &str, path: &str) -> Result<(), SftpError> {
+    Ok(session(tab_id).await?.create_dir(path).await?)
+}
+
+/// Set permission bits, e.g. 0o644
+pub async fn chmod(tab_id: &str, path: &str, mode: u32) -> Result<(), SftpError> {
+    let attrs = FileAttributes {
+        permissions: Some(mode & !S_IFMT),
+        ..FileAttributes::empty()
+    };
+    Ok(session(tab_id).await?.set_metadata(path, attrs).await?)
+}
+
+pub async fn read_file(tab_id: &str, path: &str) -> Result<Vec<u8>, SftpError> {
+    Ok(session(tab_id).await?.read(path).await?)
+}
+
+/// Create or truncate a file and write `data` to it
+pub async fn write_file(tab_id: &str, path: &str, data: &[u8]) -> Result<(), SftpError> {
+    Ok(session(tab_id).await?.write(path, data).await?)
+}
+
+/// Append a name to a remote (always `/`-separated) directory path
+pub fn join(dir: &str, name: &str) -> String {
+    if dir.is_empty() {
+        name.to_string()
+    } else if dir.ends_with('/') {
+        format!("{}{}", dir, name)
+    } else {
+        format!("{}/{}", dir, name)
+    }
+}
+
+fn file_name(path: &str) -> &str {
+    let trimmed = path.trim_end_matches('/');
+    match trimmed.rsplit_once('/') {
+        Some((_, name)) => name,
+        // "/" itself, or a relative name
+        None if trimmed.is_empty() => path,
+        None => trimmed,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_codes() {
+        let error = SftpError::Io(std::io::Error::from(std::io::ErrorKind::NotFound));
+        assert_eq!(serde_json::to_value(&error).unwrap()["code"], "not_found");
+
+        let error = SftpError::Ssh(SSHError::ConnectionFailed("Tab 1 is not connected".to_string()));
+        assert_eq!(
+            serde_json::to_value(&error).unwrap(),
+            serde_json::json!({ "code": "connection_failed", "message": "Connection failed: Tab 1 is not connected" })
+        );
+    }
+
+    #[test]
+    fn test_kind_from_mode() {
+        assert_eq!(EntryKind::from_mode(0o100644), EntryKind::File);
+        assert_eq!(EntryKind::from_mode(0o040755), EntryKind::Directory);
+        assert_eq!(EntryKind::from_mode(0o120777), EntryKind::Symlink);
+        assert_eq!(EntryKind::from_mode(0o020620), EntryKind::Other);
+    }
+
+    #[test]
+    fn test_paths() {
+        assert_eq!(join("/home/user", "notes.txt"), "/home/user/notes.txt");
+        assert_eq!(join("/", "etc"), "/etc");
+        assert_eq!(join("", "etc"), "etc");
+        assert_eq!(file_name("/var/log/"), "log");
+        assert_eq!(file_name("/"), "/");
+        assert_eq!(file_name("notes.txt"), "notes.txt");
+    }
+}
@@ -0,0 +1,504 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::time::{Duration, Instant, UNIX_EPOCH};
+use russh_sftp::client::SftpSession;
+use russh_sftp::protocol::{FileAttributes, OpenFlags};
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Emitter};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use super::transfer::{local_sha256, remote_sha256, CHUNK_SIZE, PROGRESS_INTERVAL};
+use super::{join, session, EntryKind, SftpError};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum SyncDirection {
+    /// Make the remote directory match the local one
+    Upload,
+    /// Make the local directory match the remote one
+    Download,
+}
+
+/// How to tell whether a file needs copying
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum CompareMode {
+    /// Copy when the size differs or the source is newer
+    #[default]
+    SizeMtime,
+    /// Copy when the size or SHA-256 differs (needs `sha256sum` on the server)
+    Hash,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SyncRequest {
+    pub direction: SyncDirection,
+    pub local_dir: String,
+    pub remote_dir: String,
+    #[serde(default)]
+    pub compare: CompareMode,
+    /// Remove files and directories that only exist at the destination
+    #[serde(default)]
+    pub delete: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum SyncActionKind {
+    CreateDir,
+    Create,
+    Update,
+    Delete,
+    DeleteDir,
+}
+
+/// One step of a sync, on a path relative to both roots
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SyncAction {
+    pub kind: SyncActionKind,
+    pub path: String,
+    /// Bytes to copy
+    pub size: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SyncPlan {
+    pub actions: Vec<SyncAction>,
+    pub bytes: u64,
+}
+
+/// A file or directory found while walking a tree. Symlinks are skipped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct TreeEntry {
+    is_dir: bool,
+    size: u64,
+    /// Seconds since the Unix epoch
+    mtime: u64,
+}
+
+type Tree = BTreeMap<String, TreeEntry>;
+
+/// Work out what a sync would do, without changing anything
+pub async fn plan(tab_id: &str, request: &SyncRequest) -> Result<SyncPlan, SftpError> {
+    let sftp = session(tab_id).await?;
+    let local = walk_local(&request.local_dir).await?;
+    let remote = walk_remote(&sftp, &request.remote_dir).await?;
+
+    let (source, dest) = match request.direction {
+        SyncDirection::Upload => (&local, &remote),
+        SyncDirection::Download => (&remote, &local),
+    };
+    let (mut actions, same_size) = compare_trees(source, dest, request.compare, request.delete)?;
+
+    for path in same_size {
+        let local_hash = local_sha256(&local_path(&request.local_dir, &path).to_string_lossy()).await?;
+        let remote_hash = remote_sha256(tab_id, &join(&request.remote_dir, &path)).await?;
+        if local_hash != remote_hash {
+            actions.push(SyncAction { kind: SyncActionKind::Update, size: source[&path].size, path });
+        }
+    }
+
+    sort_actions(&mut actions);
+    let bytes = actions.iter().map(|a| a.size).sum();
+    Ok(SyncPlan { actions, bytes })
+}
+
+/// Carry out a plan from `plan`, emitting `sync-progress` events. Only the
+/// actions the user saw are taken, even if the trees changed since.
+pub async fn run(app_handle: &AppHandle, tab_id: &str, request: &SyncRequest, plan: SyncPlan) -> Result<SyncPlan, SftpError> {
+    for action in &plan.actions {
+        check_path(&action.path)?;
+    }
+    let plan = SyncPlan { bytes: plan.actions.iter().map(|a| a.size).sum(), actions: plan.actions };
+    let sftp = session(tab_id).await?;
+    let mut progress = SyncProgress {
+        app_handle,
+        tab_id,
+        sync_id: uuid::Uuid::new_v4().to_string(),
+        actions: plan.actions.len(),
+        total: plan.bytes,
+        done: 0,
+        last_emit: None,
+    };
+
+    ensure_roots(&sftp, request).await?;
+
+    for (index, action) in plan.actions.iter().enumerate() {
+        progress.report(index, action, true);
+
+        let local = local_path(&request.local_dir, &action.path);
+        let remote = join(&request.remote_dir, &action.path);
+        match (action.kind, request.direction) {
+            (SyncActionKind::CreateDir, SyncDirection::Upload) => sftp.create_dir(remote).await?,
+            (SyncActionKind::CreateDir, SyncDirection::Download) => tokio::fs::create_dir(&local).await?,
+            (SyncActionKind::Create | SyncActionKind::Update, SyncDirection::Upload) => {
+                let mut reader = tokio::fs::File::open(&local).await?;
+                let flags = OpenFlags::WRITE | OpenFlags::CREATE | OpenFlags::TRUNCATE;
+                let mut writer = sftp.open_with_flags(remote.clone(), flags).await?;
+                copy(&mut reader, &mut writer, &mut progress, index, action).await?;
+
+                // Keep the source's mtime so the next size/mtime comparison matches
+                let mtime = tokio::fs::metadata(&local).await.ok().and_then(|m| modified_secs(&m)).unwrap_or(0) as u32;
+                let times = FileAttributes { atime: Some(mtime), mtime: Some(mtime), ..FileAttributes::empty() };
+                sftp.set_metadata(remote, times).await?;
+            }
+            (SyncActionKind::Create | SyncActionKind::Update, SyncDirection::Download) => {
+                let mtime = sftp.metadata(remote.clone()).await?.mtime.unwrap_or(0);
+                let mut reader = sftp.open_with_flags(remote, OpenFlags::READ).await?;
+                let mut writer = tokio::fs::File::create(&local).await?;
+                copy(&mut reader, &mut writer, &mut progress, index, action).await?;
+
+                // Same as for uploads: keep the source's mtime
+                let modified = UNIX_EPOCH + Duration::from_secs(mtime as u64);
+                writer.into_std().await.set_modified(modified)?;
+            }
+            (SyncActionKind::Delete, SyncDirection::Upload) => sftp.remove_file(remote).await?,
+            (SyncActionKind::Delete, SyncDirection::Download) => tokio::fs::remove_file(&local).await?,
+            (SyncActionKind::DeleteDir, SyncDirection::Upload) => sftp.remove_dir(remote).await?,
+            (SyncActionKind::DeleteDir, SyncDirection::Download) => tokio::fs::remove_dir(&local).await?,
+        }
+    }
+
+    progress.finish();
+    Ok(plan)
+}
+
+/// A plan comes back from the UI, so make sure its paths stay inside the roots
+fn check_path(path: &str) -> Result<(), SftpError> {
+    if path.starts_with('/') || path.split('/').any(|part| part.is_empty() || part == "." || part == "..") {
+        return Err(SftpError::Transfer(format!("{:?} is not a path inside the synced directories", path)));
+    }
+    Ok(())
+}
+
+/// Create the destination root if this is the first sync into it
+async fn ensure_roots(sftp: &SftpSession, request: &SyncRequest) -> Result<(), SftpError> {
+    match request.direction {
+        SyncDirection::Upload => {
+            if sftp.metadata(request.remote_dir.clone()).await.is_err() {
+                sftp.create_dir(request.remote_dir.clone()).await?;
+            }
+        }
+        SyncDirection::Download => tokio::fs::create_dir_all(&request.local_dir).await?,
+    }
+    Ok(())
+}
+
+/// Compare a source tree against a destination tree. Same-size files are
+/// returned separately in hash mode, since hashing needs I/O.
+fn compare_trees(
+    source: &Tree,
+    dest: &Tree,
+    compare: CompareMode,
+    delete: bool,
+) -> Result<(Vec<SyncAction>, Vec<String>), SftpError> {
+    let mut actions = Vec::new();
+    let mut same_size = Vec::new();
+
+    for (path, src) in source {
+        let kind = match dest.get(path) {
+            None if src.is_dir => Some(SyncActionKind::CreateDir),
+            None => Some(SyncActionKind::Create),
+            Some(dst) if dst.is_dir != src.is_dir => {
+                return Err(SftpError::Transfer(format!(
+                    "{} is a directory on one side and a file on the other",
+                    path
+                )));
+            }
+            Some(_) if src.is_dir => None,
+            Some(dst) if dst.size != src.size => Some(SyncActionKind::Update),
+            Some(dst) => match compare {
+                CompareMode::SizeMtime => (src.mtime > dst.mtime).then_some(SyncActionKind::Update),
+                CompareMode::Hash => {
+                    same_size.push(path.clone());
+                    None
+                }
+            },
+        };
+
+        if let Some(kind) = kind {
+            let size = if src.is_dir { 0 } else { src.size };
+            actions.push(SyncAction { kind, path: path.clone(), size });
+        }
+    }
+
+    if delete {
+        for (path, dst) in dest {
+            if !source.contains_key(path) {
+                let kind = if dst.is_dir { SyncActionKind::DeleteDir } else { SyncActionKind::Delete };
+                actions.push(SyncAction { kind, path: path.clone(), size: 0 });
+            }
+        }
+    }
+
+    Ok((actions, same_size))
+}
+
+/// Order actions so parents are created before their children and emptied
+/// before they are removed
+fn sort_actions(actions: &mut [SyncAction]) {
+    actions.sort_by(|a, b| {
+        let phase = |action: &SyncAction| match action.kind {
+            SyncActionKind::CreateDir => 0,
+            SyncActionKind::Create | SyncActionKind::Update => 1,
+            SyncActionKind::Delete => 2,
+            SyncActionKind::DeleteDir => 3,
+        };
+        phase(a).cmp(&phase(b)).then_with(|| {
+            if a.kind == SyncActionKind::DeleteDir {
+                b.path.cmp(&a.path)
+            } else {
+                a.path.cmp(&b.path)
+            }
+        })
+    });
+}
+
+async fn walk_local(root: &str) -> Result<Tree, SftpError> {
+    let mut tree = Tree::new();
+    if tokio::fs::metadata(root).await.is_err() {
+        return Ok(tree);
+    }
+
+    let mut pending = vec![String::new()];
+    while let Some(dir) = pending.pop() {
+        let mut entries = tokio::fs::read_dir(local_path(root, &dir)).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let metadata = tokio::fs::symlink_metadata(entry.path()).await?;
+            if metadata.file_type().is_symlink() {
+                continue;
+            }
+
+            let path = join(&dir, &entry.file_name().to_string_lossy());
+            let is_dir = metadata.is_dir();
+            if is_dir {
+                pending.push(path.clone());
+            }
+            tree.insert(path, TreeEntry {
+                is_dir,
+                size: if is_dir { 0 } else { metadata.len() },
+                mtime: modified_secs(&metadata).unwrap_or(0),
+            });
+        }
+    }
+    Ok(tree)
+}
+
+async fn walk_remote(sftp: &SftpSession, root: &str) -> Result<Tree, SftpError> {
+    let mut tree = Tree::new();
+    if sftp.metadata(root.to_string()).await.is_err() {
+        return Ok(tree);
+    }
+
+    let mut pending = vec![String::new()];
+    while let Some(dir) = pending.pop() {
+        for entry in sftp.read_dir(join(root, &dir)).await? {
+            let name = entry.file_name();
+            if name == "." || name == ".." {
+                continue;
+            }
+
+            let attrs = entry.metadata();
+            let is_dir = match EntryKind::from_mode(attrs.permissions.unwrap_or(0)) {
+                EntryKind::Directory => true,
+                EntryKind::File => false,
+                EntryKind::Symlink | EntryKind::Other => continue,
+            };
+
+            let path = join(&dir, &name);
+            if is_dir {
+                pending.push(path.clone());
+            }
+            tree.insert(path, TreeEntry {
+                is_dir,
+                size: if is_dir { 0 } else { attrs.size.unwrap_or(0) },
+                mtime: attrs.mtime.unwrap_or(0) as u64,
+            });
+        }
+    }
+    Ok(tree)
+}
+
+fn local_path(root: &str, relative: &str) -> PathBuf {
+    let mut path = PathBuf::from(root);
+    path.extend(relative.split('/').filter(|part| !part.is_empty()));
+    path
+}
+
+fn modified_secs(metadata: &std::fs::Metadata) -> Option<u64> {
+    let modified = metadata.modified().ok()?;
+    Some(modified.duration_since(UNIX_EPOCH).ok()?.as_secs())
+}
+
+async fn copy<R, W>(
+    reader: &mut R,
+    writer: &mut W,
+    progress: &mut SyncProgress<'_>,
+    index: usize,
+    action: &SyncAction,
+) -> Result<(), SftpError>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut buf = vec![0; CHUNK_SIZE];
+    loop {
+        let n = reader.read(&mut buf).await?;
+        if n == 0 {
+            writer.shutdown().await?;
+            return Ok(());
+        }
+        writer.write_all(&buf[..n]).await?;
+        progress.done += n as u64;
+        progress.report(index, action, false);
+    }
+}
+
+struct SyncProgress<'a> {
+    app_handle: &'a AppHandle,
+    tab_id: &'a str,
+    sync_id: String,
+    actions: usize,
+    total: u64,
+    done: u64,
+    last_emit: Option<Instant>,
+}
+
+impl SyncProgress<'_> {
+    /// Report progress, at most every `PROGRESS_INTERVAL` unless `force`d
+    fn report(&mut self, index: usize, action: &SyncAction, force: bool) {
+        if !force && matches!(self.last_emit, Some(at) if at.elapsed() < PROGRESS_INTERVAL) {
+            return;
+        }
+        self.last_emit = Some(Instant::now());
+
+        let _ = self.app_handle.emit("sync-progress", serde_json::json!({
+            "sync_id": self.sync_id,
+            "tab_id": self.tab_id,
+            "action": index,
+            "actions": self.actions,
+            "kind": action.kind,
+            "path": action.path,
+            "bytes": self.done,
+            "total": self.total,
+            "done": false,
+        }));
+    }
+
+    fn finish(&self) {
+        let _ = self.app_handle.emit("sync-progress", serde_json::json!({
+            "sync_id": self.sync_id,
+            "tab_id": self.tab_id,
+            "action": self.actions,
+            "actions": self.actions,
+            "bytes": self.done,
+            "total": self.total,
+            "done": true,
+        }));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(size: u64, mtime: u64) -> TreeEntry {
+        TreeEntry { is_dir: false, size, mtime }
+    }
+
+    fn dir() -> TreeEntry {
+        TreeEntry { is_dir: true, size: 0, mtime: 0 }
+    }
+
+    fn tree(entries: &[(&str, TreeEntry)]) -> Tree {
+        entries.iter().map(|(path, entry)| (path.to_string(), *entry)).collect()
+    }
+
+    fn plan_of(source: &Tree, dest: &Tree, compare: CompareMode, delete: bool) -> Vec<(SyncActionKind, String)> {
+        let (mut actions, _) = compare_trees(source, dest, compare, delete).unwrap();
+        sort_actions(&mut actions);
+        actions.into_iter().map(|a| (a.kind, a.path)).collect()
+    }
+
+    #[test]
+    fn test_plan_creates_updates_and_deletes() {
+        let source = tree(&[
+            ("conf", dir()),
+            ("conf/app.yml", file(10, 200)),
+            ("conf/new.yml", file(5, 100)),
+            ("same.txt", file(3, 100)),
+            ("stale.txt", file(3, 100)),
+        ]);
+        let dest = tree(&[
+            ("conf", dir()),
+            ("conf/app.yml", file(12, 100)),
+            ("same.txt", file(3, 100)),
+            ("stale.txt", file(3, 50)),
+            ("old", dir()),
+            ("old/leftover", file(1, 1)),
+        ]);
+
+        assert_eq!(
+            plan_of(&source, &dest, CompareMode::SizeMtime, true),
+            vec![
+                (SyncActionKind::Update, "conf/app.yml".to_string()),
+                (SyncActionKind::Create, "conf/new.yml".to_string()),
+                (SyncActionKind::Update, "stale.txt".to_string()),
+                (SyncActionKind::Delete, "old/leftover".to_string()),
+                (SyncActionKind::DeleteDir, "old".to_string()),
+            ]
+        );
+
+        // Without `delete`, extra files at the destination are left alone
+        assert_eq!(plan_of(&source, &dest, CompareMode::SizeMtime, false).len(), 3);
+    }
+
+    #[test]
+    fn test_dirs_created_parent_first_and_removed_child_first() {
+        let source = tree(&[("a", dir()), ("a/b", dir()), ("a/b/c", file(1, 1))]);
+        assert_eq!(
+            plan_of(&source, &Tree::new(), CompareMode::SizeMtime, false),
+            vec![
+                (SyncActionKind::CreateDir, "a".to_string()),
+                (SyncActionKind::CreateDir, "a/b".to_string()),
+                (SyncActionKind::Create, "a/b/c".to_string()),
+            ]
+        );
+        assert_eq!(
+            plan_of(&Tree::new(), &source, CompareMode::SizeMtime, true),
+            vec![
+                (SyncActionKind::Delete, "a/b/c".to_string()),
+                (SyncActionKind::DeleteDir, "a/b".to_string()),
+                (SyncActionKind::DeleteDir, "a".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_hash_mode_defers_same_size_files() {
+        let source = tree(&[("a", file(3, 100)), ("b", file(4, 100))]);
+        let dest = tree(&[("a", file(3, 900)), ("b", file(5, 100))]);
+
+        let (actions, same_size) = compare_trees(&source, &dest, CompareMode::Hash, false).unwrap();
+        assert_eq!(actions, vec![SyncAction { kind: SyncActionKind::Update, path: "b".to_string(), size: 4 }]);
+        assert_eq!(same_size, vec!["a".to_string()]);
+    }
+
+    #[test]
+    fn test_plan_paths_stay_inside_roots() {
+        assert!(check_path("conf/app.yml").is_ok());
+        assert!(check_path("../etc/passwd").is_err());
+        assert!(check_path("conf/../../etc").is_err());
+        assert!(check_path("/etc/passwd").is_err());
+        assert!(check_path("").is_err());
+    }
+
+    #[test]
+    fn test_type_conflict_is_an_error() {
+        let source = tree(&[("x", dir())]);
+        let dest = tree(&[("x", file(1, 1))]);
+        assert!(compare_trees(&source, &dest, CompareMode::SizeMtime, true).is_err());
+    }
+}
@@ -0,0 +1,591 @@
+use std::collections::{HashMap, HashSet};
+use std::io::SeekFrom;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use russh_sftp::protocol::OpenFlags;
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Emitter};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::watch;
+use crate::ssh::{self, SSHError};
+use super::SftpError;
+
+/// Bytes per SFTP read/write request
+pub(super) const CHUNK_SIZE: usize = 32 * 1024;
+/// Minimum time between progress events of one transfer
+pub(super) const PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+/// Longest wait for the server to hash a file
+const CHECKSUM_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum TransferDirection {
+    Upload,
+    Download,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum TransferState {
+    Queued,
+    Running,
+    Paused,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TransferRequest {
+    pub direction: TransferDirection,
+    pub local_path: String,
+    pub remote_path: String,
+    /// Also compare SHA-256 checksums (runs `sha256sum` on the server)
+    #[serde(default)]
+    pub verify_checksum: bool,
+}
+
+/// A transfer as reported to the UI
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TransferInfo {
+    pub id: String,
+    pub tab_id: String,
+    pub direction: TransferDirection,
+    pub local_path: String,
+    pub remote_path: String,
+    pub verify_checksum: bool,
+    pub state: TransferState,
+    pub bytes: u64,
+    pub total: Option<u64>,
+    pub error: Option<String>,
+    /// Bytes this transfer had written when it was paused or failed. Only
+    /// then is what's at the destination ours to continue from.
+    pub resume_from: Option<u64>,
+}
+
+/// What the UI last asked a running transfer to do
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Control {
+    Run,
+    Pause,
+    Cancel,
+}
+
+struct Transfer {
+    info: TransferInfo,
+    /// Position in the tab's queue
+    seq: u64,
+    control: watch::Sender<Control>,
+}
+
+#[derive(Default)]
+struct Queue {
+    transfers: HashMap<String, Transfer>,
+    /// Tabs with a worker running their queue
+    workers: HashSet<String>,
+    next_seq: u64,
+}
+
+lazy_static::lazy_static! {
+    static ref QUEUE: Mutex<Queue> = Mutex::new(Queue::default());
+}
+
+/// How a transfer run ended, short of an error
+enum Outcome {
+    Completed,
+    Paused,
+    Cancelled,
+}
+
+/// Add a transfer to the tab's queue. Each tab works through its queue one
+/// transfer at a time, in the background, for as long as the tab is connected.
+pub fn enqueue(app_handle: &AppHandle, tab_id: &str, request: TransferRequest) -> TransferInfo {
+    let info = TransferInfo {
+        id: uuid::Uuid::new_v4().to_string(),
+        tab_id: tab_id.to_string(),
+        direction: request.direction,
+        local_path: request.local_path,
+        remote_path: request.remote_path,
+        verify_checksum: request.verify_checksum,
+        state: TransferState::Queued,
+        bytes: 0,
+        total: None,
+        error: None,
+        resume_from: None,
+    };
+
+    let mut queue = QUEUE.lock().unwrap();
+    let seq = queue.next_seq;
+    queue.next_seq += 1;
+    let (control, _) = watch::channel(Control::Run);
+    queue.transfers.insert(info.id.clone(), Transfer { info: info.clone(), seq, control });
+    ensure_worker(&mut queue, app_handle, tab_id);
+    info
+}
+
+pub fn pause(app_handle: &AppHandle, id: &str) -> Result<(), SftpError> {
+    update(app_handle, id, |transfer| match transfer.info.state {
+        TransferState::Queued => transfer.info.state = TransferState::Paused,
+        // The worker stops after the current chunk
+        TransferState::Running => {
+            transfer.control.send_replace(Control::Pause);
+        }
+        _ => {}
+    })
+}
+
+pub fn cancel(app_handle: &AppHandle, id: &str) -> Result<(), SftpError> {
+    update(app_handle, id, |transfer| match transfer.info.state {
+        TransferState::Queued | TransferState::Paused | TransferState::Failed => {
+            transfer.info.state = TransferState::Cancelled;
+        }
+        TransferState::Running => {
+            transfer.control.send_replace(Control::Cancel);
+        }
+        _ => {}
+    })
+}
+
+/// Put a paused or failed transfer back in the queue. It continues from
+/// what has already been written.
+pub fn resume(app_handle: &AppHandle, id: &str) -> Result<(), SftpError> {
+    let mut queue = QUEUE.lock().unwrap();
+    let seq = queue.next_seq;
+    let transfer = queue.transfers.get_mut(id).ok_or_else(|| not_found(id))?;
+    if !matches!(transfer.info.state, TransferState::Paused | TransferState::Failed) {
+        return Ok(());
+    }
+
+    transfer.info.state = TransferState::Queued;
+    transfer.info.error = None;
+    transfer.seq = seq;
+    transfer.control.send_replace(Control::Run);
+    let info = transfer.info.clone();
+    let tab_id = info.tab_id.clone();
+
+    queue.next_seq += 1;
+    ensure_worker(&mut queue, app_handle, &tab_id);
+    emit_status(app_handle, &info);
+    Ok(())
+}
+
+/// Transfers of a tab, or of every tab, in queue order
+pub fn list(tab_id: Option<&str>) -> Vec<TransferInfo> {
+    let queue = QUEUE.lock().unwrap();
+    let mut transfers: Vec<&Transfer> = queue
+        .transfers
+        .values()
+        .filter(|t| match tab_id {
+            Some(tab_id) => t.info.tab_id == tab_id,
+            None => true,
+        })
+        .collect();
+    transfers.sort_by_key(|t| t.seq);
+    transfers.into_iter().map(|t| t.info.clone()).collect()
+}
+
+/// Forget completed, failed and cancelled transfers
+pub fn clear_finished(tab_id: Option<&str>) {
+    QUEUE.lock().unwrap().transfers.retain(|_, t| {
+        tab_id.is_some_and(|tab_id| t.info.tab_id != tab_id)
+            || matches!(t.info.state, TransferState::Queued | TransferState::Running | TransferState::Paused)
+    });
+}
+
+fn update(app_handle: &AppHandle, id: &str, change: impl FnOnce(&mut Transfer)) -> Result<(), SftpError> {
+    let info = {
+        let mut queue = QUEUE.lock().unwrap();
+        let transfer = queue.transfers.get_mut(id).ok_or_else(|| not_found(id))?;
+        change(transfer);
+        transfer.info.clone()
+    };
+    emit_status(app_handle, &info);
+    Ok(())
+}
+
+fn not_found(id: &str) -> SftpError {
+    SftpError::Transfer(format!("No transfer {}", id))
+}
+
+fn ensure_worker(queue: &mut Queue, app_handle: &AppHandle, tab_id: &str) {
+    if queue.workers.insert(tab_id.to_string()) {
+        tokio::spawn(run_worker(app_handle.clone(), tab_id.to_string()));
+    }
+}
+
+async fn run_worker(app_handle: AppHandle, tab_id: String) {
+    loop {
+        let next = {
+            let mut guard = QUEUE.lock().unwrap();
+            let queue = &mut *guard;
+            let next = queue
+                .transfers
+                .values_mut()
+                .filter(|t| t.info.tab_id == tab_id && t.info.state == TransferState::Queued)
+                .min_by_key(|t| t.seq);
+
+            match next {
+                Some(transfer) => {
+                    transfer.info.state = TransferState::Running;
+                    (transfer.info.clone(), transfer.control.subscribe())
+                }
+                None => {
+                    // Checked under the same lock `enqueue` takes, so nothing is left behind
+                    queue.workers.remove(&tab_id);
+                    return;
+                }
+            }
+        };
+        let (info, control) = next;
+        emit_status(&app_handle, &info);
+
+        let result = run_transfer(&app_handle, &info, control).await;
+
+        let info = {
+            let mut queue = QUEUE.lock().unwrap();
+            let Some(transfer) = queue.transfers.get_mut(&info.id) else { continue };
+            transfer.info.state = match result {
+                Ok(Outcome::Completed) => TransferState::Completed,
+                Ok(Outcome::Paused) => TransferState::Paused,
+                Ok(Outcome::Cancelled) => TransferState::Cancelled,
+                Err(e) => {
+                    transfer.info.error = Some(e.to_string());
+                    TransferState::Failed
+                }
+            };
+            transfer.info.resume_from = match transfer.info.state {
+                // Progress lags the writes a little, which only means
+                // copying a few bytes again
+                TransferState::Paused | TransferState::Failed => Some(transfer.info.bytes),
+                _ => None,
+            };
+            transfer.info.clone()
+        };
+        emit_status(&app_handle, &info);
+    }
+}
+
+async fn run_transfer(
+    app_handle: &AppHandle,
+    info: &TransferInfo,
+    control: watch::Receiver<Control>,
+) -> Result<Outcome, SftpError> {
+    let sftp = super::session(&info.tab_id).await?;
+    let remote_size = sftp.metadata(&info.remote_path).await.ok().and_then(|m| m.size);
+    let local_size = tokio::fs::metadata(&info.local_path).await.ok().map(|m| m.len());
+
+    let (total, existing) = match info.direction {
+        TransferDirection::Download => (remote_size.unwrap_or(0), local_size),
+        TransferDirection::Upload => {
+            let total = local_size.ok_or_else(|| {
+                SftpError::Transfer(format!("{} does not exist", info.local_path))
+            })?;
+            (total, remote_size)
+        }
+    };
+    let offset = resume_offset(info.resume_from, existing, total);
+
+    let mut progress = Progress::new(app_handle, info, offset, total);
+    let outcome = match info.direction {
+        TransferDirection::Download => {
+            let mut remote = sftp.open_with_flags(&info.remote_path, OpenFlags::READ).await?;
+            remote.seek(SeekFrom::Start(offset)).await?;
+
+            let mut local = tokio::fs::OpenOptions::new()
+                .create(true)
+                .write(true)
+                .truncate(offset == 0)
+                .open(&info.local_path)
+                .await?;
+            local.set_len(offset).await?;
+            local.seek(SeekFrom::Start(offset)).await?;
+
+            copy(&mut remote, &mut local, control, &mut progress).await?
+        }
+        TransferDirection::Upload => {
+            let mut flags = OpenFlags::WRITE | OpenFlags::CREATE;
+            if offset == 0 {
+                flags |= OpenFlags::TRUNCATE;
+            }
+            let mut remote = sftp.open_with_flags(&info.remote_path, flags).await?;
+            remote.seek(SeekFrom::Start(offset)).await?;
+
+            let mut local = tokio::fs::File::open(&info.local_path).await?;
+            local.seek(SeekFrom::Start(offset)).await?;
+
+            copy(&mut local, &mut remote, control, &mut progress).await?
+        }
+    };
+    progress.finish();
+
+    if let Outcome::Completed = outcome {
+        verify(info, total).await?;
+    }
+    Ok(outcome)
+}
+
+/// Where to continue a transfer. Only a prefix this transfer wrote itself
+/// is kept; anything else at the destination is overwritten from the start.
+fn resume_offset(resume_from: Option<u64>, existing: Option<u64>, total: u64) -> u64 {
+    match (resume_from, existing) {
+        // The destination still holds at least what we wrote before
+        (Some(written), Some(size)) if written <= size && written <= total => written,
+        _ => 0,
+    }
+}
+
+/// Copy in chunks until EOF, stopping early if the UI pauses or cancels
+async fn copy<R, W>(
+    reader: &mut R,
+    writer: &mut W,
+    mut control: watch::Receiver<Control>,
+    progress: &mut Progress<'_>,
+) -> Result<Outcome, SftpError>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut buf = vec![0; CHUNK_SIZE];
+    loop {
+        match *control.borrow_and_update() {
+            Control::Run => {}
+            Control::Pause => {
+                writer.flush().await?;
+                return Ok(Outcome::Paused);
+            }
+            Control::Cancel => return Ok(Outcome::Cancelled),
+        }
+
+        let n = reader.read(&mut buf).await?;
+        if n == 0 {
+            writer.shutdown().await?;
+            return Ok(Outcome::Completed);
+        }
+        writer.write_all(&buf[..n]).await?;
+        progress.advance(n as u64);
+    }
+}
+
+/// Check the destination against the source by size and, if asked, by SHA-256
+async fn verify(info: &TransferInfo, expected_size: u64) -> Result<(), SftpError> {
+    let sftp = super::session(&info.tab_id).await?;
+    let remote_size = sftp.metadata(&info.remote_path).await?.size.unwrap_or(0);
+    let local_size = tokio::fs::metadata(&info.local_path).await?.len();
+
+    if remote_size != expected_size || local_size != expected_size {
+        return Err(SftpError::Transfer(format!(
+            "Size mismatch after transfer: local {} bytes, remote {} bytes",
+            local_size, remote_size
+        )));
+    }
+
+    if info.verify_checksum {
+        let local = local_sha256(&info.local_path).await?;
+        let remote = remote_sha256(&info.tab_id, &info.remote_path).await?;
+        if local != remote {
+            return Err(SftpError::Transfer(format!(
+                "Checksum mismatch: local {}, remote {}",
+                local, remote
+            )));
+        }
+    }
+    Ok(())
+}
+
+pub(super) async fn local_sha256(path: &str) -> Result<String, SftpError> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut context = ring::digest::Context::new(&ring::digest::SHA256);
+    let mut buf = vec![0; CHUNK_SIZE];
+    loop {
+        let n = file.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        context.update(&buf[..n]);
+    }
+    Ok(hex(context.finish().as_ref()))
+}
+
+/// SFTP has no standard checksum request, so ask the server's `sha256sum`
+pub(super) async fn remote_sha256(tab_id: &str, path: &str) -> Result<String, SftpError> {
+    let handle = ssh::connection_handle(tab_id).await?;
+    let command = format!("sha256sum -- {}", shell_quote(path));
+    let output = ssh::exec::run(&handle, &command, CHECKSUM_TIMEOUT).await?;
+    if output.timed_out {
+        return Err(SftpError::Transfer(format!("sha256sum of {} timed out after {:?}", path, CHECKSUM_TIMEOUT)));
+    }
+
+    match output.stdout.split_whitespace().next() {
+        Some(digest) if digest.len() == 64 => Ok(digest.to_lowercase()),
+        _ => Err(SftpError::Ssh(SSHError::Unsupported(
+            "remote checksums (sha256sum not found on the server)".to_string(),
+        ))),
+    }
+}
+
+/// Quote a string for a POSIX shell command line
+pub fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn emit_status(app_handle: &AppHandle, info: &TransferInfo) {
+    let _ = app_handle.emit("transfer-status", info);
+}
+
+/// Tracks and reports the progress of one transfer run
+struct Progress<'a> {
+    app_handle: &'a AppHandle,
+    info: &'a TransferInfo,
+    bytes: u64,
+    total: u64,
+    rate: RateMeter,
+    last_emit: Option<Instant>,
+}
+
+impl<'a> Progress<'a> {
+    fn new(app_handle: &'a AppHandle, info: &'a TransferInfo, offset: u64, total: u64) -> Self {
+        let mut progress = Self {
+            app_handle,
+            info,
+            bytes: offset,
+            total,
+            rate: RateMeter::new(Instant::now(), offset),
+            last_emit: None,
+        };
+        progress.report();
+        progress
+    }
+
+    fn advance(&mut self, bytes: u64) {
+        self.bytes += bytes;
+        self.rate.update(Instant::now(), self.bytes);
+        if !matches!(self.last_emit, Some(at) if at.elapsed() < PROGRESS_INTERVAL) {
+            self.report();
+        }
+    }
+
+    fn finish(&mut self) {
+        self.report();
+    }
+
+    fn report(&mut self) {
+        self.last_emit = Some(Instant::now());
+
+        if let Some(transfer) = QUEUE.lock().unwrap().transfers.get_mut(&self.info.id) {
+            transfer.info.bytes = self.bytes;
+            transfer.info.total = Some(self.total);
+        }
+
+        let rate = self.rate.rate();
+        let _ = self.app_handle.emit("transfer-progress", serde_json::json!({
+            "transfer_id": self.info.id,
+            "tab_id": self.info.tab_id,
+            "bytes": self.bytes,
+            "total": self.total,
+            "rate": rate.round() as u64,
+            "eta_secs": eta(self.total.saturating_sub(self.bytes), rate),
+        }));
+    }
+}
+
+/// Transfer rate in bytes per second, smoothed so the ETA doesn't jump
+/// around with every chunk
+struct RateMeter {
+    last_at: Instant,
+    last_bytes: u64,
+    rate: f64,
+}
+
+impl RateMeter {
+    /// How much each new sample moves the average
+    const SMOOTHING: f64 = 0.3;
+    /// Shorter intervals are accumulated rather than sampled
+    const MIN_SAMPLE: Duration = Duration::from_millis(100);
+
+    fn new(now: Instant, bytes: u64) -> Self {
+        Self { last_at: now, last_bytes: bytes, rate: 0.0 }
+    }
+
+    fn update(&mut self, now: Instant, bytes: u64) {
+        let elapsed = now.saturating_duration_since(self.last_at);
+        if elapsed < Self::MIN_SAMPLE {
+            return;
+        }
+
+        let sample = (bytes - self.last_bytes) as f64 / elapsed.as_secs_f64();
+        self.rate = if self.rate == 0.0 {
+            sample
+        } else {
+            self.rate + Self::SMOOTHING * (sample - self.rate)
+        };
+        self.last_at = now;
+        self.last_bytes = bytes;
+    }
+
+    fn rate(&self) -> f64 {
+        self.rate
+    }
+}
+
+/// Seconds left at the given rate, if there is a rate to go by
+fn eta(remaining: u64, rate: f64) -> Option<u64> {
+    (rate > 0.0).then(|| (remaining as f64 / rate).ceil() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate_meter_smooths_samples() {
+        let start = Instant::now();
+        let mut meter = RateMeter::new(start, 1000);
+
+        // Too soon to take a sample
+        meter.update(start + Duration::from_millis(50), 2000);
+        assert_eq!(meter.rate(), 0.0);
+
+        meter.update(start + Duration::from_secs(1), 101_000);
+        assert_eq!(meter.rate(), 100_000.0);
+
+        // A second at 200 KB/s moves the average part of the way
+        meter.update(start + Duration::from_secs(2), 301_000);
+        assert_eq!(meter.rate(), 130_000.0);
+    }
+
+    #[test]
+    fn test_eta() {
+        assert_eq!(eta(1000, 0.0), None);
+        assert_eq!(eta(1000, 300.0), Some(4));
+        assert_eq!(eta(0, 300.0), Some(0));
+    }
+
+    #[test]
+    fn test_resume_offset() {
+        // A file already at the destination isn't ours to continue, even
+        // when it's the same size
+        assert_eq!(resume_offset(None, Some(1000), 1000), 0);
+        assert_eq!(resume_offset(None, Some(400), 1000), 0);
+
+        assert_eq!(resume_offset(Some(400), Some(400), 1000), 400);
+        // Bytes written after the last progress report are written again
+        assert_eq!(resume_offset(Some(400), Some(432), 1000), 400);
+        // Truncated or replaced since we paused
+        assert_eq!(resume_offset(Some(400), Some(100), 1000), 0);
+        assert_eq!(resume_offset(Some(400), None, 1000), 0);
+        // The source shrank
+        assert_eq!(resume_offset(Some(400), Some(400), 300), 0);
+    }
+
+    #[test]
+    fn test_shell_quote() {
+        assert_eq!(shell_quote("/tmp/a b"), "'/tmp/a b'");
+        assert_eq!(shell_quote("it's"), r"'it'\''s'");
+    }
+}
@@ -0,0 +1,148 @@
+use std::path::PathBuf;
+#[cfg(unix)]
+use std::sync::atomic::AtomicU64;
+use base64::{Engine as _, engine::general_purpose};
+use russh::{client, Channel};
+use serde::Serialize;
+#[cfg(unix)]
+use super::forward;
+use super::keys::{read_string, read_u32};
+use super::known_hosts::fingerprint_base64;
+use super::SSHError;
+
+const SSH_AGENTC_REQUEST_IDENTITIES: u8 = 11;
+const SSH_AGENT_IDENTITIES_ANSWER: u8 = 12;
+
+/// A key held by the local ssh-agent
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AgentIdentity {
+    pub key_type: String,
+    pub fingerprint: String,
+    pub comment: String,
+    #[serde(skip)]
+    pub key_base64: String,
+}
+
+impl AgentIdentity {
+    /// Whether this identity is the one pinned by fingerprint (with or
+    /// without the `SHA256:` prefix) or by comment
+    pub fn matches(&self, pin: &str) -> bool {
+        let pin = pin.trim();
+        self.fingerprint == pin
+            || self.fingerprint.strip_prefix("SHA256:") == Some(pin)
+            || self.comment == pin
+    }
+}
+
+/// Path of the agent socket from `$SSH_AUTH_SOCK`
+pub fn socket_path() -> Result<PathBuf, SSHError> {
+    std::env::var_os("SSH_AUTH_SOCK")
+        .map(PathBuf::from)
+        .ok_or_else(|| SSHError::Agent("SSH_AUTH_SOCK is not set".to_string()))
+}
+
+/// List the agent's identities together with their comments, which the
+/// russh agent client doesn't expose
+#[cfg(unix)]
+pub async fn list_identities() -> Result<Vec<AgentIdentity>, SSHError> {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let mut stream = tokio::net::UnixStream::connect(socket_path()?).await?;
+    stream.write_all(&[0, 0, 0, 1, SSH_AGENTC_REQUEST_IDENTITIES]).await?;
+
+    let len = stream.read_u32().await? as usize;
+    let mut body = vec![0; len];
+    stream.read_exact(&mut body).await?;
+
+    parse_identities(&body).ok_or_else(|| SSHError::Agent("Malformed identities answer".to_string()))
+}
+
+#[cfg(not(unix))]
+pub async fn list_identities() -> Result<Vec<AgentIdentity>, SSHError> {
+    Err(SSHError::Unsupported("ssh-agent on this platform".to_string()))
+}
+
+/// Serve an `auth-agent@openssh.com` channel the server opened, by
+/// relaying it to the local agent
+#[cfg(unix)]
+pub fn serve_forwarded(channel: Channel<client::Msg>, host: String) {
+    println!("Agent forwarding: {} opened a channel to the local ssh-agent", host);
+
+    tokio::spawn(async move {
+        let socket = match socket_path() {
+            Ok(path) => tokio::net::UnixStream::connect(path).await.map_err(SSHError::from),
+            Err(e) => Err(e),
+        };
+        match socket {
+            Ok(socket) => {
+                let (sent, received) = (AtomicU64::new(0), AtomicU64::new(0));
+                forward::relay(socket, channel, &received, &sent).await;
+            }
+            Err(e) => eprintln!("Agent forwarding for {} failed: {}", host, e),
+        }
+    });
+}
+
+#[cfg(not(unix))]
+pub fn serve_forwarded(_channel: Channel<client::Msg>, host: String) {
+    // Dropping the channel closes it
+    eprintln!("Agent forwarding requested by {}, but ssh-agent is not supported on this platform", host);
+}
+
+fn parse_identities(body: &[u8]) -> Option<Vec<AgentIdentity>> {
+    let (&kind, mut rest) = body.split_first()?;
+    if kind != SSH_AGENT_IDENTITIES_ANSWER {
+        return None;
+    }
+
+    let count = read_u32(&mut rest)?;
+    let mut identities = Vec::new();
+    for _ in 0..count {
+        let blob = read_string(&mut rest)?;
+        let comment = read_string(&mut rest)?;
+
+        let mut blob_rest = blob;
+        let key_type = read_string(&mut blob_rest)?;
+        let key_base64 = general_purpose::STANDARD.encode(blob);
+
+        identities.push(AgentIdentity {
+            key_type: String::from_utf8_lossy(key_type).to_string(),
+            fingerprint: fingerprint_base64(&key_base64)?,
+            comment: String::from_utf8_lossy(comment).to_string(),
+            key_base64,
+        });
+    }
+    Some(identities)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ssh_string(data: &[u8]) -> Vec<u8> {
+        let mut out = (data.len() as u32).to_be_bytes().to_vec();
+        out.extend_from_slice(data);
+        out
+    }
+
+    #[test]
+    fn test_parse_identities_and_pin() {
+        let mut blob = ssh_string(b"ssh-ed25519");
+        blob.extend(ssh_string(&[7; 32]));
+
+        let mut body = vec![SSH_AGENT_IDENTITIES_ANSWER, 0, 0, 0, 1];
+        body.extend(ssh_string(&blob));
+        body.extend(ssh_string(b"deploy@laptop"));
+
+        let identities = parse_identities(&body).unwrap();
+        assert_eq!(identities.len(), 1);
+
+        let identity = &identities[0];
+        assert_eq!(identity.key_type, "ssh-ed25519");
+        assert!(identity.matches("deploy@laptop"));
+        assert!(identity.matches(&identity.fingerprint));
+        assert!(identity.matches(identity.fingerprint.trim_start_matches("SHA256:")));
+        assert!(!identity.matches("someone@else"));
+    }
+}
@@ -0,0 +1,219 @@
+use std::sync::Arc;
+use russh::client;
+use russh::client::KeyboardInteractiveAuthResponse;
+use russh_keys::decode_secret_key;
+use russh_keys::key::KeyPair;
+use serde::Deserialize;
+use sqlx::SqlitePool;
+use tauri::AppHandle;
+use crate::db::{async_db, AuthMethod, SSHSession};
+use super::lifecycle::{self, Stage};
+use super::{keys, prompt, Client, SSHError};
+
+/// How many times the user may retry a wrong passphrase
+const MAX_PASSPHRASE_ATTEMPTS: u32 = 3;
+
+/// Everything authentication needs besides the session being authenticated
+pub struct AuthContext<'a> {
+    pub pool: &'a SqlitePool,
+    pub app_handle: &'a AppHandle,
+    pub tab_id: &'a str,
+}
+
+#[derive(Debug, Deserialize)]
+struct PassphraseAnswer {
+    passphrase: Option<String>,
+    #[serde(default)]
+    remember: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct KeyboardInteractiveAnswer {
+    responses: Option<Vec<String>>,
+}
+
+/// Run the session's auth chain. Servers that require several methods
+/// (e.g. publickey, then an OTP over keyboard-interactive) reject the first
+/// ones with partial success, so we keep going until one completes.
+pub async fn authenticate(
+    session: &mut client::Handle<Client>,
+    config: &SSHSession,
+    ctx: &AuthContext<'_>,
+) -> Result<(), SSHError> {
+    let chain = config.auth_chain();
+    let mut agent_error = None;
+
+    for method in &chain {
+        lifecycle::emit(ctx.app_handle, ctx.tab_id, Stage::Authenticating { host: &config.host, method: *method });
+        let authenticated = match method {
+            AuthMethod::Password => {
+                session.authenticate_password(&config.username, config.password.as_deref().unwrap_or("")).await?
+            }
+            AuthMethod::PublicKey => {
+                let key_pair = load_private_key(config, ctx).await?;
+                session.authenticate_publickey(&config.username, Arc::new(key_pair)).await?
+            }
+            AuthMethod::Agent => match authenticate_agent(session, config).await {
+                Ok(authenticated) => authenticated,
+                // No agent, or nothing in it to offer: move on like after a rejected key
+                Err(e @ (SSHError::Agent(_) | SSHError::Unsupported(_) | SSHError::Io(_))) => {
+                    agent_error = Some(e);
+                    false
+                }
+                Err(e) => return Err(e),
+            },
+            AuthMethod::KeyboardInteractive => authenticate_keyboard_interactive(session, config, ctx).await?,
+        };
+
+        if authenticated {
+            return Ok(());
+        }
+    }
+
+    // With nothing else tried, why the agent couldn't be used says more
+    if let (Some(e), [AuthMethod::Agent]) = (agent_error, chain.as_slice()) {
+        return Err(e);
+    }
+    let tried: Vec<&str> = chain.iter().map(AuthMethod::name).collect();
+    Err(SSHError::AuthFailed(tried.join(", ")))
+}
+
+/// Forward each round of server prompts to the UI and send back the answers
+async fn authenticate_keyboard_interactive(
+    session: &mut client::Handle<Client>,
+    config: &SSHSession,
+    ctx: &AuthContext<'_>,
+) -> Result<bool, SSHError> {
+    let mut response = session
+        .authenticate_keyboard_interactive_start(&config.username, None)
+        .await?;
+
+    loop {
+        match response {
+            KeyboardInteractiveAuthResponse::Success => return Ok(true),
+            KeyboardInteractiveAuthResponse::Failure => return Ok(false),
+            KeyboardInteractiveAuthResponse::InfoRequest { name, instructions, prompts } => {
+                // Some servers send an empty round before or after the real prompts
+                let responses = if prompts.is_empty() {
+                    Vec::new()
+                } else {
+                    let prompts: Vec<serde_json::Value> = prompts
+                        .iter()
+                        .map(|p| serde_json::json!({ "prompt": p.prompt, "echo": p.echo }))
+                        .collect();
+
+                    let answer: KeyboardInteractiveAnswer = prompt::ask(ctx.app_handle, "keyboard-interactive-prompt", serde_json::json!({
+                        "tab_id": ctx.tab_id,
+                        "name": name,
+                        "instructions": instructions,
+                        "prompts": prompts,
+                    })).await?;
+
+                    answer.responses.ok_or_else(|| {
+                        SSHError::PromptCancelled("Keyboard-interactive authentication cancelled".to_string())
+                    })?
+                };
+
+                response = session.authenticate_keyboard_interactive_respond(responses).await?;
+            }
+        }
+    }
+}
+
+/// Try each ssh-agent identity in turn, or only the pinned one
+#[cfg(unix)]
+async fn authenticate_agent(session: &mut client::Handle<Client>, config: &SSHSession) -> Result<bool, SSHError> {
+    use russh_keys::agent::client::AgentClient;
+    use russh_keys::PublicKeyBase64;
+    use super::agent;
+
+    let pin = config.agent_identity.as_deref().map(str::trim).filter(|pin| !pin.is_empty());
+    let identities = match pin {
+        Some(_) => agent::list_identities().await?,
+        None => Vec::new(),
+    };
+
+    let mut agent_client = AgentClient::connect_env()
+        .await
+        .map_err(|e| SSHError::Agent(e.to_string()))?;
+    let keys = agent_client
+        .request_identities()
+        .await
+        .map_err(|e| SSHError::Agent(e.to_string()))?;
+
+    let mut offered = 0;
+    for key in keys {
+        if let Some(pin) = pin {
+            let key_base64 = key.public_key_base64();
+            let pinned = identities
+                .iter()
+                .any(|identity| identity.key_base64 == key_base64 && identity.matches(pin));
+            if !pinned {
+                continue;
+            }
+        }
+
+        offered += 1;
+        let (returned_client, result) = session.authenticate_future(&config.username, key, agent_client).await;
+        agent_client = returned_client;
+        if result.map_err(|e| SSHError::Agent(e.to_string()))? {
+            return Ok(true);
+        }
+    }
+
+    if offered == 0 {
+        return Err(SSHError::Agent(match pin {
+            Some(pin) => format!("No agent identity matches '{}'", pin),
+            None => "The agent has no identities".to_string(),
+        }));
+    }
+    Ok(false)
+}
+
+#[cfg(not(unix))]
+async fn authenticate_agent(_session: &mut client::Handle<Client>, _config: &SSHSession) -> Result<bool, SSHError> {
+    Err(SSHError::Unsupported("ssh-agent on this platform".to_string()))
+}
+
+/// Load the session's private key, asking for a passphrase if it is encrypted
+async fn load_private_key(config: &SSHSession, ctx: &AuthContext<'_>) -> Result<KeyPair, SSHError> {
+    let key_path = config.private_key.as_deref().unwrap_or("");
+    let expanded_path = shellexpand::tilde(key_path).to_string();
+    let contents = tokio::fs::read_to_string(&expanded_path).await?;
+
+    if !keys::is_encrypted(&contents) {
+        return Ok(decode_secret_key(&contents, None)?);
+    }
+
+    if let Some(stored) = async_db::get_key_passphrase(ctx.pool, &config.id).await? {
+        if let Some(key_pair) = keys::decrypt(&contents, &stored) {
+            return Ok(key_pair);
+        }
+        // The key was re-encrypted since we remembered its passphrase
+        async_db::set_key_passphrase(ctx.pool, &config.id, None).await?;
+    }
+
+    let mut error: Option<&str> = None;
+    for attempt in 1..=MAX_PASSPHRASE_ATTEMPTS {
+        let answer: PassphraseAnswer = prompt::ask(ctx.app_handle, "key-passphrase-prompt", serde_json::json!({
+            "tab_id": ctx.tab_id,
+            "key_path": key_path,
+            "attempt": attempt,
+            "error": error,
+        })).await?;
+
+        let Some(passphrase) = answer.passphrase else {
+            return Err(SSHError::PromptCancelled("Passphrase entry cancelled".to_string()));
+        };
+
+        if let Some(key_pair) = keys::decrypt(&contents, &passphrase) {
+            if answer.remember {
+                async_db::set_key_passphrase(ctx.pool, &config.id, Some(&passphrase)).await?;
+            }
+            return Ok(key_pair);
+        }
+        error = Some("Wrong passphrase");
+    }
+
+    Err(SSHError::WrongPassphrase(key_path.to_string()))
+}
@@ -0,0 +1,174 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use russh::client;
+use tokio::net::{lookup_host, TcpStream};
+use crate::db::{async_db, ForwardKind, PortForward, SSHSession};
+use super::auth::{self, AuthContext};
+use super::forward::{self, ForwardManager, RemoteRoutes};
+use super::lifecycle::{self, Stage};
+use super::throttle::{GatedStream, ReadGate};
+use super::x11::X11Forwarding;
+use super::{Client, SSHError};
+
+/// An authenticated SSH connection, possibly tunnelled through jump hosts
+pub struct Connection {
+    /// Shared with tasks that open their own channels, such as port forwards
+    pub handle: Arc<client::Handle<Client>>,
+    /// The session's remote forwards and whether the server agreed to each
+    pub remote_forwards: Vec<(PortForward, Result<(), SSHError>)>,
+    /// Forwards running over the connection. Every tab on it shares them,
+    /// and they stop when the connection closes, not when a tab does.
+    pub forwards: tokio::sync::Mutex<ForwardManager>,
+    /// Cookies for X11 forwarding, if the session asked for it and there
+    /// is a local display to forward to
+    pub x11: Option<Arc<X11Forwarding>>,
+    /// Held by a tab whose renderer is behind, to stop reading the server
+    pub read_gate: ReadGate,
+    /// Hops the connection runs through, which must stay open with it
+    _jump_hosts: Vec<client::Handle<Client>>,
+}
+
+/// Connect and authenticate to `config`, going through its jump hosts in
+/// order. Each hop uses the auth settings of its own saved session.
+pub async fn open(ctx: &AuthContext<'_>, config: &SSHSession) -> Result<Connection, SSHError> {
+    let mut hops = Vec::with_capacity(config.jump_hosts.len());
+    for (index, hop_id) in config.jump_hosts.iter().enumerate() {
+        if hop_id == &config.id || config.jump_hosts[..index].contains(hop_id) {
+            return Err(SSHError::ConnectionFailed(format!("Jump host {} repeats a host in the chain", index + 1)));
+        }
+        let hop = async_db::get_session(ctx.pool, hop_id)
+            .await
+            .map_err(|e| jump_error(index, hop_id, e.into()))?;
+        hops.push(hop);
+    }
+
+    // Everything arrives over the first hop's socket, so gating it stops
+    // the whole chain
+    let read_gate = ReadGate::default();
+    let mut jump_hosts: Vec<client::Handle<Client>> = Vec::with_capacity(hops.len());
+    for (index, hop) in hops.iter().enumerate() {
+        let handle = dial(ctx, hop, jump_hosts.last(), RemoteRoutes::default(), None, &read_gate)
+            .await
+            .map_err(|e| jump_error(index, &hop.name, e))?;
+        jump_hosts.push(handle);
+    }
+
+    let x11 = if config.forward_x11 {
+        match X11Forwarding::from_env().await {
+            Ok(x11) => Some(Arc::new(x11)),
+            Err(e) => {
+                // Not worth failing the login over
+                eprintln!("X11 forwarding for {} is off: {}", config.host, e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    let remote_routes = RemoteRoutes::default();
+    let mut handle = dial(ctx, config, jump_hosts.last(), remote_routes.clone(), x11.clone(), &read_gate).await?;
+
+    // `tcpip-forward` needs the handle to ourselves, so send it before sharing
+    let mut remote_forwards = Vec::new();
+    let requested = config.port_forwards.iter().filter(|f| f.kind == ForwardKind::Remote && f.auto_start);
+    for remote in requested {
+        let result = forward::request_remote(&mut handle, remote).await;
+        remote_forwards.push((remote.clone(), result));
+    }
+
+    let handle = Arc::new(handle);
+    Ok(Connection {
+        forwards: tokio::sync::Mutex::new(ForwardManager::new(handle.clone(), remote_routes)),
+        handle,
+        remote_forwards,
+        x11,
+        read_gate,
+        _jump_hosts: jump_hosts,
+    })
+}
+
+/// Open a transport to `config` (directly, or as a `direct-tcpip` channel
+/// through `via`) and authenticate on it. A direct transport stops reading
+/// while `read_gate` is held.
+async fn dial(
+    ctx: &AuthContext<'_>,
+    config: &SSHSession,
+    via: Option<&client::Handle<Client>>,
+    remote_routes: RemoteRoutes,
+    x11: Option<Arc<X11Forwarding>>,
+    read_gate: &ReadGate,
+) -> Result<client::Handle<Client>, SSHError> {
+    let client_config = Arc::new(client_config(config));
+    let client_handler = Client {
+        app_handle: ctx.app_handle.clone(),
+        tab_id: ctx.tab_id.to_string(),
+        host: config.host.clone(),
+        port: config.port,
+        remote_routes,
+        forward_agent: config.forward_agent,
+        x11,
+    };
+
+    let mut session = match via {
+        Some(jump) => {
+            let address = format!("{}:{}", config.host, config.port);
+            lifecycle::emit(ctx.app_handle, ctx.tab_id, Stage::Connecting { host: &config.host, address });
+            let channel = jump
+                .channel_open_direct_tcpip(config.host.clone(), config.port as u32, "127.0.0.1", 0)
+                .await?;
+            lifecycle::emit(ctx.app_handle, ctx.tab_id, Stage::Handshaking { host: &config.host });
+            client::connect_stream(client_config, channel.into_stream(), client_handler).await?
+        }
+        None => {
+            let stream = GatedStream::new(connect_tcp(ctx, config).await?, read_gate.clone());
+            lifecycle::emit(ctx.app_handle, ctx.tab_id, Stage::Handshaking { host: &config.host });
+            client::connect_stream(client_config, stream, client_handler).await?
+        }
+    };
+
+    auth::authenticate(&mut session, config, ctx).await?;
+    Ok(session)
+}
+
+/// Resolve the host and connect to the first address that answers
+async fn connect_tcp(ctx: &AuthContext<'_>, config: &SSHSession) -> Result<TcpStream, SSHError> {
+    lifecycle::emit(ctx.app_handle, ctx.tab_id, Stage::Resolving { host: &config.host, port: config.port });
+    let addresses: Vec<SocketAddr> = lookup_host((config.host.as_str(), config.port))
+        .await
+        .map_err(|e| SSHError::Resolve(format!("{}: {}", config.host, e)))?
+        .collect();
+
+    let mut last_error = SSHError::Resolve(config.host.clone());
+    for address in addresses {
+        lifecycle::emit(ctx.app_handle, ctx.tab_id, Stage::Connecting { host: &config.host, address: address.to_string() });
+        match TcpStream::connect(address).await {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_error = e.into(),
+        }
+    }
+    Err(last_error)
+}
+
+/// Transport settings for `config`. Keepalives are what notice a connection
+/// silently dropped by a NAT or firewall; without them the tab just hangs.
+fn client_config(config: &SSHSession) -> client::Config {
+    let keepalive = &config.keepalive;
+    client::Config {
+        keepalive_interval: match keepalive.interval_secs {
+            0 => None,
+            secs => Some(Duration::from_secs(secs as u64)),
+        },
+        keepalive_max: keepalive.max_missed.max(1) as usize,
+        ..Default::default()
+    }
+}
+
+fn jump_error(index: usize, name: &str, source: SSHError) -> SSHError {
+    SSHError::JumpHost {
+        hop: index + 1,
+        name: name.to_string(),
+        source: Box::new(source),
+    }
+}
@@ -0,0 +1,118 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use russh::{client, ChannelMsg};
+use serde::Serialize;
+use sqlx::SqlitePool;
+use tauri::AppHandle;
+use crate::db::async_db;
+use super::auth::AuthContext;
+use super::{connection_handle, pool, signal_name, tab_session_id, Client, SSHError};
+
+/// Used when the caller doesn't give a timeout
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+/// Output beyond this much per stream is dropped
+const MAX_OUTPUT: usize = 16 * 1024 * 1024;
+
+/// Outcome of a command run on its own `exec` channel
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExecResult {
+    pub stdout: String,
+    pub stderr: String,
+    pub exit_status: Option<u32>,
+    /// Signal that killed the command, e.g. "KILL"
+    pub exit_signal: Option<String>,
+    /// The command was still running at the timeout and its channel was closed
+    pub timed_out: bool,
+    /// Some output was dropped for exceeding the size limit
+    pub truncated: bool,
+    pub duration_ms: u64,
+}
+
+/// Run a command on a saved session without touching any terminal. Uses
+/// the connection of `tab_id` if given and opened from `session_id`, and
+/// otherwise the session's pooled connection, which is opened for the
+/// command if no tab holds one.
+pub async fn exec_command(
+    pool: &SqlitePool,
+    app_handle: &AppHandle,
+    session_id: &str,
+    command: &str,
+    timeout: Duration,
+    tab_id: Option<&str>,
+) -> Result<ExecResult, SSHError> {
+    if let Some(tab_id) = tab_id {
+        // A stale tab id may now belong to another host
+        if tab_session_id(tab_id).await.as_deref() == Some(session_id) {
+            if let Ok(handle) = connection_handle(tab_id).await {
+                return run(&handle, command, timeout).await;
+            }
+        }
+    }
+
+    // Prompts (host key, passphrase) go to the requesting tab, if there is one
+    let prompt_tab = match tab_id {
+        Some(tab_id) => tab_id.to_string(),
+        None => format!("exec-{}", uuid::Uuid::new_v4()),
+    };
+    let config = async_db::get_session(pool, session_id).await?;
+    let ctx = AuthContext { pool, app_handle, tab_id: &prompt_tab };
+    let lease = pool::acquire(&ctx, &config).await?;
+
+    run(&lease.connection.handle, command, timeout).await
+}
+
+/// Run `command` on its own channel of `handle`, closing it at `timeout`
+pub async fn run(handle: &Arc<client::Handle<Client>>, command: &str, timeout: Duration) -> Result<ExecResult, SSHError> {
+    let started = Instant::now();
+    let mut channel = handle.channel_open_session().await?;
+    channel.exec(true, command).await?;
+
+    let mut result = ExecResult::default();
+    let mut stdout = Vec::new();
+    let mut stderr = Vec::new();
+    let deadline = tokio::time::Instant::now() + timeout;
+
+    loop {
+        let msg = match tokio::time::timeout_at(deadline, channel.wait()).await {
+            Ok(Some(msg)) => msg,
+            Ok(None) => break,
+            Err(_) => {
+                result.timed_out = true;
+                let _ = channel.close().await;
+                break;
+            }
+        };
+
+        match msg {
+            ChannelMsg::Data { ref data } => {
+                result.truncated |= append(&mut stdout, data);
+            }
+            // Extended data type 1 is stderr (RFC 4254 section 5.2)
+            ChannelMsg::ExtendedData { ref data, ext: 1 } => {
+                result.truncated |= append(&mut stderr, data);
+            }
+            ChannelMsg::ExitStatus { exit_status } => {
+                result.exit_status = Some(exit_status);
+            }
+            ChannelMsg::ExitSignal { signal_name: signal, .. } => {
+                result.exit_signal = Some(signal_name(&signal));
+            }
+            // The exit status usually follows EOF, so wait for Close
+            ChannelMsg::Close => break,
+            _ => {}
+        }
+    }
+
+    result.stdout = String::from_utf8_lossy(&stdout).into_owned();
+    result.stderr = String::from_utf8_lossy(&stderr).into_owned();
+    result.duration_ms = started.elapsed().as_millis() as u64;
+    Ok(result)
+}
+
+/// Append up to the output limit. Returns true if anything was dropped.
+fn append(buffer: &mut Vec<u8>, data: &[u8]) -> bool {
+    let room = MAX_OUTPUT.saturating_sub(buffer.len());
+    buffer.extend_from_slice(&data[..data.len().min(room)]);
+    data.len() > room
+}
@@ -0,0 +1,185 @@
+use std::collections::HashMap;
+use std::time::Duration;
+use regex::Regex;
+use tokio::sync::broadcast;
+use super::output::Utf8Decoder;
+use super::SSHError;
+
+/// Unmatched output kept for matching; older text is dropped
+const MAX_BUFFER: usize = 64 * 1024;
+
+/// Where the ANSI escape stripper is inside a sequence
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum Escape {
+    #[default]
+    None,
+    /// Just saw ESC
+    Start,
+    /// `ESC [` ... up to a final byte
+    Csi,
+    /// `ESC ]` ... up to BEL or `ESC \`
+    Osc,
+    /// ESC inside an OSC, possibly starting its `ESC \` terminator
+    OscEnd,
+}
+
+/// Terminal output as plain text for pattern matching: decoded, without
+/// ANSI escape sequences or carriage returns, and consumed up to each
+/// match so the same prompt isn't matched twice
+#[derive(Debug, Default)]
+pub struct ExpectBuffer {
+    decoder: Utf8Decoder,
+    escape: Escape,
+    text: String,
+}
+
+/// Text a pattern matched, with its capture groups
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExpectMatch {
+    pub text: String,
+    /// Numbered groups, starting at group 1
+    pub groups: Vec<Option<String>>,
+    pub named: HashMap<String, String>,
+}
+
+impl ExpectBuffer {
+    pub fn feed(&mut self, data: &[u8]) {
+        let decoded = self.decoder.decode(data);
+        for c in decoded.chars() {
+            self.escape = match (self.escape, c) {
+                (Escape::None, '\x1b') => Escape::Start,
+                (Escape::None, '\r') => Escape::None,
+                (Escape::None, c) => {
+                    self.text.push(c);
+                    Escape::None
+                }
+                (Escape::Start, '[') => Escape::Csi,
+                (Escape::Start, ']') => Escape::Osc,
+                // Two-character sequences such as `ESC =`
+                (Escape::Start, _) => Escape::None,
+                (Escape::Csi, '\x40'..='\x7e') => Escape::None,
+                (Escape::Csi, _) => Escape::Csi,
+                (Escape::Osc, '\x07') => Escape::None,
+                (Escape::Osc, '\x1b') => Escape::OscEnd,
+                (Escape::Osc, _) => Escape::Osc,
+                (Escape::OscEnd, '\\') => Escape::None,
+                (Escape::OscEnd, _) => Escape::Osc,
+            };
+        }
+
+        if self.text.len() > MAX_BUFFER {
+            let mut cut = self.text.len() - MAX_BUFFER;
+            while !self.text.is_char_boundary(cut) {
+                cut += 1;
+            }
+            self.text.drain(..cut);
+        }
+    }
+
+    /// Output received since the last match
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Find whichever pattern matches earliest in the buffer, and consume
+    /// the text up to the end of that match
+    pub fn take_match(&mut self, patterns: &[Regex]) -> Option<(usize, ExpectMatch)> {
+        let (index, captures) = patterns
+            .iter()
+            .enumerate()
+            .filter_map(|(index, pattern)| pattern.captures(&self.text).map(|captures| (index, captures)))
+            .min_by_key(|(_, captures)| captures.get(0).map_or(0, |m| m.start()))?;
+
+        let found = ExpectMatch {
+            text: captures[0].to_string(),
+            groups: captures.iter().skip(1).map(|group| group.map(|m| m.as_str().to_string())).collect(),
+            named: patterns[index]
+                .capture_names()
+                .flatten()
+                .filter_map(|name| Some((name.to_string(), captures.name(name)?.as_str().to_string())))
+                .collect(),
+        };
+        let end = captures.get(0).map_or(0, |m| m.end());
+        self.text.drain(..end);
+        Some((index, found))
+    }
+}
+
+/// Wait until one of `patterns` shows up in the tab's output. Returns the
+/// index of the pattern that matched, or None on timeout.
+pub async fn wait_for(
+    output: &mut broadcast::Receiver<Vec<u8>>,
+    buffer: &mut ExpectBuffer,
+    patterns: &[Regex],
+    timeout: Duration,
+) -> Result<Option<(usize, ExpectMatch)>, SSHError> {
+    let deadline = tokio::time::Instant::now() + timeout;
+
+    loop {
+        if let Some(found) = buffer.take_match(patterns) {
+            return Ok(Some(found));
+        }
+
+        match tokio::time::timeout_at(deadline, output.recv()).await {
+            Ok(Ok(data)) => buffer.feed(&data),
+            // We fell behind a burst of output; carry on with what follows
+            Ok(Err(broadcast::error::RecvError::Lagged(_))) => {}
+            Ok(Err(broadcast::error::RecvError::Closed)) => {
+                return Err(SSHError::ConnectionFailed("The tab was closed".to_string()));
+            }
+            Err(_) => return Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_strips_escapes_across_chunks() {
+        let mut buffer = ExpectBuffer::default();
+        buffer.feed(b"\x1b]0;user@web: ~\x07\x1b[01;3");
+        buffer.feed(b"2muser@web\x1b[00m:~$ \r\n");
+        assert_eq!(buffer.text(), "user@web:~$ \n");
+    }
+
+    #[test]
+    fn test_take_match_consumes_through_match() {
+        let mut buffer = ExpectBuffer::default();
+        buffer.feed(b"Last login: today\n[sudo] password for deploy: ");
+
+        let patterns = [Regex::new(r"\$ $").unwrap(), Regex::new(r"password for (?P<user>\w+):").unwrap()];
+        let (index, found) = buffer.take_match(&patterns).unwrap();
+        assert_eq!(index, 1);
+        assert_eq!(found.groups, vec![Some("deploy".to_string())]);
+        assert_eq!(found.named["user"], "deploy");
+        assert_eq!(buffer.text(), " ");
+        assert!(buffer.take_match(&patterns).is_none());
+    }
+
+    #[test]
+    fn test_earliest_match_wins() {
+        let mut buffer = ExpectBuffer::default();
+        buffer.feed("грешка\nroot@web:~# ".as_bytes());
+
+        let patterns = [Regex::new(r"# $").unwrap(), Regex::new("грешка").unwrap()];
+        assert_eq!(buffer.take_match(&patterns).unwrap().0, 1);
+        assert_eq!(buffer.take_match(&patterns).unwrap().0, 0);
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_times_out() {
+        let (tx, mut rx) = broadcast::channel(8);
+        let mut buffer = ExpectBuffer::default();
+        let patterns = [Regex::new(r"\$ $").unwrap()];
+
+        tx.send(b"loading...".to_vec()).unwrap();
+        let found = wait_for(&mut rx, &mut buffer, &patterns, Duration::from_millis(20)).await.unwrap();
+        assert!(found.is_none());
+
+        tx.send(b"\nuser@web:~$ ".to_vec()).unwrap();
+        let found = wait_for(&mut rx, &mut buffer, &patterns, Duration::from_millis(20)).await.unwrap();
+        assert_eq!(found.unwrap().1.text, "$ ");
+    }
+}
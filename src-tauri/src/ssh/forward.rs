@@ -0,0 +1,381 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+use russh::client;
+use russh::Channel;
+use serde::Serialize;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::watch;
+use tokio::task::JoinSet;
+use crate::db::{ForwardKind, PortForward};
+use super::{socks, Client, SSHError};
+
+/// Live counters for one forward, shared with its connection tasks
+#[derive(Debug, Default)]
+struct ForwardStats {
+    /// Bytes accepted on the listening side and sent on to the target
+    bytes_sent: AtomicU64,
+    /// Bytes the target sent back to the listening side
+    bytes_received: AtomicU64,
+    active_connections: AtomicUsize,
+    total_connections: AtomicU64,
+}
+
+/// Snapshot of a running forward, as reported to the UI
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ForwardStatus {
+    pub forward: PortForward,
+    /// Address listened on, which tells the real port when a local
+    /// `bind_port` was 0
+    pub local_addr: String,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    pub active_connections: usize,
+    pub total_connections: u64,
+}
+
+/// What the handler needs to serve a `forwarded-tcpip` channel
+#[derive(Clone)]
+pub struct RemoteRoute {
+    forward: PortForward,
+    stats: Arc<ForwardStats>,
+    shutdown: watch::Receiver<()>,
+}
+
+/// Remote forwards of one connection, by the port the server listens on
+pub type RemoteRoutes = Arc<RwLock<HashMap<u32, RemoteRoute>>>;
+
+struct RunningForward {
+    forward: PortForward,
+    local_addr: String,
+    stats: Arc<ForwardStats>,
+    /// Dropping this ends the listener and every connection of the forward
+    _shutdown: watch::Sender<()>,
+}
+
+impl RunningForward {
+    fn status(&self) -> ForwardStatus {
+        ForwardStatus {
+            forward: self.forward.clone(),
+            local_addr: self.local_addr.clone(),
+            bytes_sent: self.stats.bytes_sent.load(Ordering::Relaxed),
+            bytes_received: self.stats.bytes_received.load(Ordering::Relaxed),
+            active_connections: self.stats.active_connections.load(Ordering::Relaxed),
+            total_connections: self.stats.total_connections.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// The forwards running over one connection. Dropping it stops them all.
+pub struct ForwardManager {
+    handle: Arc<client::Handle<Client>>,
+    routes: RemoteRoutes,
+    running: HashMap<String, RunningForward>,
+}
+
+impl ForwardManager {
+    pub fn new(handle: Arc<client::Handle<Client>>, routes: RemoteRoutes) -> Self {
+        Self { handle, routes, running: HashMap::new() }
+    }
+
+    /// Bind the listener of a `Local` or `Dynamic` forward and start accepting connections
+    pub async fn start(&mut self, mut forward: PortForward) -> Result<ForwardStatus, SSHError> {
+        if forward.kind == ForwardKind::Remote {
+            // russh needs exclusive access to the handle to send `tcpip-forward`,
+            // which we only have while connecting
+            return Err(SSHError::Forward("Remote forwards are requested when the session connects".to_string()));
+        }
+        assign_id(&mut forward);
+        self.ensure_not_running(&forward)?;
+
+        let listener = TcpListener::bind((forward.bind_address.as_str(), forward.bind_port))
+            .await
+            .map_err(|e| {
+                SSHError::Forward(format!("Cannot listen on {}:{}: {}", forward.bind_address, forward.bind_port, e))
+            })?;
+        let local_addr = listener.local_addr()?.to_string();
+
+        let stats = Arc::new(ForwardStats::default());
+        let (shutdown, shutdown_rx) = watch::channel(());
+        tokio::spawn(accept_loop(
+            listener,
+            self.handle.clone(),
+            forward.clone(),
+            stats.clone(),
+            shutdown_rx,
+        ));
+
+        Ok(self.insert(RunningForward { forward, local_addr, stats, _shutdown: shutdown }))
+    }
+
+    /// Start routing connections for a `Remote` forward the server has
+    /// already agreed to (see [`request_remote`])
+    pub fn adopt_remote(&mut self, mut forward: PortForward) -> Result<ForwardStatus, SSHError> {
+        assign_id(&mut forward);
+        self.ensure_not_running(&forward)?;
+
+        let stats = Arc::new(ForwardStats::default());
+        let (shutdown, shutdown_rx) = watch::channel(());
+        let route = RemoteRoute { forward: forward.clone(), stats: stats.clone(), shutdown: shutdown_rx };
+        self.routes.write().unwrap().insert(forward.bind_port as u32, route);
+
+        let local_addr = format!("{}:{}", forward.bind_address, forward.bind_port);
+        Ok(self.insert(RunningForward { forward, local_addr, stats, _shutdown: shutdown }))
+    }
+
+    /// Stop a forward and drop its open connections. Returns false if it wasn't running.
+    pub async fn stop(&mut self, id: &str) -> Result<bool, SSHError> {
+        let Some(running) = self.running.remove(id) else {
+            return Ok(false);
+        };
+
+        let forward = &running.forward;
+        if forward.kind == ForwardKind::Remote {
+            self.routes.write().unwrap().remove(&(forward.bind_port as u32));
+            self.handle
+                .cancel_tcpip_forward(forward.bind_address.clone(), forward.bind_port as u32)
+                .await?;
+        }
+        Ok(true)
+    }
+
+    pub fn list(&self) -> Vec<ForwardStatus> {
+        let mut statuses: Vec<ForwardStatus> = self.running.values().map(RunningForward::status).collect();
+        statuses.sort_by_key(|status| (status.forward.kind == ForwardKind::Remote, status.forward.bind_port));
+        statuses
+    }
+
+    fn ensure_not_running(&self, forward: &PortForward) -> Result<(), SSHError> {
+        if self.running.contains_key(&forward.id) {
+            return Err(SSHError::Forward(format!("Forward {} is already running", forward.id)));
+        }
+        Ok(())
+    }
+
+    fn insert(&mut self, running: RunningForward) -> ForwardStatus {
+        let status = running.status();
+        self.running.insert(status.forward.id.clone(), running);
+        status
+    }
+}
+
+fn assign_id(forward: &mut PortForward) {
+    if forward.id.is_empty() {
+        forward.id = uuid::Uuid::new_v4().to_string();
+    }
+}
+
+/// Ask the server to listen for a `Remote` forward
+pub async fn request_remote(handle: &mut client::Handle<Client>, forward: &PortForward) -> Result<(), SSHError> {
+    if forward.bind_port == 0 {
+        // Inbound channels are routed by port, and russh doesn't tell us
+        // which one the server picked
+        return Err(SSHError::Forward("Remote forwards need an explicit port".to_string()));
+    }
+
+    let accepted = handle
+        .tcpip_forward(forward.bind_address.clone(), forward.bind_port as u32)
+        .await?;
+    if !accepted {
+        return Err(SSHError::Forward(format!(
+            "Server refused to listen on {}:{}",
+            forward.bind_address, forward.bind_port
+        )));
+    }
+    Ok(())
+}
+
+/// Connect a `forwarded-tcpip` channel opened by the server to the local
+/// target of its forward
+pub fn route_remote(routes: &RemoteRoutes, channel: Channel<client::Msg>, connected_port: u32, originator: String) {
+    let route = routes.read().unwrap().get(&connected_port).cloned();
+    let Some(RemoteRoute { forward, stats, mut shutdown }) = route else {
+        // Dropping the channel closes it
+        eprintln!("No remote forward for port {}, rejecting connection from {}", connected_port, originator);
+        return;
+    };
+
+    tokio::spawn(async move {
+        track(&stats, async {
+            match TcpStream::connect((forward.target_host.as_str(), forward.target_port)).await {
+                Ok(socket) => {
+                    tokio::select! {
+                        _ = relay(socket, channel, &stats.bytes_received, &stats.bytes_sent) => {}
+                        _ = shutdown.changed() => {}
+                    }
+                }
+                Err(e) => {
+                    eprintln!(
+                        "Remote forward {} could not reach {}:{}: {}",
+                        forward.id, forward.target_host, forward.target_port, e
+                    );
+                }
+            }
+        })
+        .await;
+    });
+}
+
+async fn accept_loop(
+    listener: TcpListener,
+    handle: Arc<client::Handle<Client>>,
+    forward: PortForward,
+    stats: Arc<ForwardStats>,
+    mut shutdown: watch::Receiver<()>,
+) {
+    // Owned here so returning closes every connection too
+    let mut connections = JoinSet::new();
+
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => {
+                let (socket, peer) = match accepted {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        eprintln!("Port forward {} accept failed: {}", forward.id, e);
+                        continue;
+                    }
+                };
+                let handle = handle.clone();
+                let forward = forward.clone();
+                let stats = stats.clone();
+                match forward.kind {
+                    ForwardKind::Dynamic => connections.spawn(socks_connection(socket, peer, handle, forward, stats)),
+                    _ => connections.spawn(forward_connection(socket, peer, handle, forward, stats)),
+                };
+            }
+            // Reap finished connections so the set doesn't grow forever
+            Some(_) = connections.join_next(), if !connections.is_empty() => {}
+            _ = shutdown.changed() => break,
+        }
+    }
+}
+
+async fn forward_connection(
+    socket: TcpStream,
+    peer: SocketAddr,
+    handle: Arc<client::Handle<Client>>,
+    forward: PortForward,
+    stats: Arc<ForwardStats>,
+) {
+    track(&stats, async {
+        let channel = handle
+            .channel_open_direct_tcpip(
+                forward.target_host.clone(),
+                forward.target_port as u32,
+                peer.ip().to_string(),
+                peer.port() as u32,
+            )
+            .await;
+
+        match channel {
+            Ok(channel) => relay(socket, channel, &stats.bytes_sent, &stats.bytes_received).await,
+            Err(e) => {
+                eprintln!(
+                    "Port forward {} could not reach {}:{}: {}",
+                    forward.id, forward.target_host, forward.target_port, e
+                );
+            }
+        }
+    })
+    .await;
+}
+
+/// Serve one SOCKS client: read its CONNECT request and tunnel to the target
+/// it asked for. Host names go to the server as-is, so DNS resolves remotely.
+async fn socks_connection(
+    mut socket: TcpStream,
+    peer: SocketAddr,
+    handle: Arc<client::Handle<Client>>,
+    forward: PortForward,
+    stats: Arc<ForwardStats>,
+) {
+    track(&stats, async {
+        let request = match socks::read_request(&mut socket).await {
+            Ok(request) => request,
+            Err(e) => {
+                eprintln!("SOCKS proxy {}: bad request from {}: {}", forward.id, peer, e);
+                return;
+            }
+        };
+
+        let channel = handle
+            .channel_open_direct_tcpip(
+                request.host.clone(),
+                request.port as u32,
+                peer.ip().to_string(),
+                peer.port() as u32,
+            )
+            .await;
+
+        match channel {
+            Ok(channel) => {
+                if socks::reply(&mut socket, request.version, true).await.is_ok() {
+                    relay(socket, channel, &stats.bytes_sent, &stats.bytes_received).await;
+                }
+            }
+            Err(e) => {
+                let _ = socks::reply(&mut socket, request.version, false).await;
+                eprintln!(
+                    "SOCKS proxy {} could not reach {}:{}: {}",
+                    forward.id, request.host, request.port, e
+                );
+            }
+        }
+    })
+    .await;
+}
+
+/// Count a connection as active while it runs
+async fn track(stats: &ForwardStats, connection: impl std::future::Future<Output = ()>) {
+    stats.total_connections.fetch_add(1, Ordering::Relaxed);
+    stats.active_connections.fetch_add(1, Ordering::Relaxed);
+    connection.await;
+    stats.active_connections.fetch_sub(1, Ordering::Relaxed);
+}
+
+/// Copy data both ways between a local socket and an SSH channel
+pub(super) async fn relay<S>(socket: S, channel: Channel<client::Msg>, to_channel: &AtomicU64, from_channel: &AtomicU64)
+where
+    S: AsyncRead + AsyncWrite,
+{
+    relay_stream(socket, channel.into_stream(), to_channel, from_channel).await;
+}
+
+/// `relay` for a channel already turned into a stream, e.g. after reading
+/// a handshake off it
+pub(super) async fn relay_stream<L, R>(local: L, remote: R, to_remote: &AtomicU64, from_remote: &AtomicU64)
+where
+    L: AsyncRead + AsyncWrite,
+    R: AsyncRead + AsyncWrite,
+{
+    let (local_read, local_write) = tokio::io::split(local);
+    let (remote_read, remote_write) = tokio::io::split(remote);
+
+    // Each direction is shut down on its own EOF, so half-closed
+    // connections keep working; an error on either side ends both
+    let _ = tokio::try_join!(
+        pipe(local_read, remote_write, to_remote),
+        pipe(remote_read, local_write, from_remote),
+    );
+}
+
+/// Copy until EOF or an error, counting bytes as they go through
+async fn pipe<R, W>(mut reader: R, mut writer: W, counter: &AtomicU64) -> std::io::Result<()>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut buf = vec![0; 32 * 1024];
+    loop {
+        let n = reader.read(&mut buf).await?;
+        if n == 0 {
+            return writer.shutdown().await;
+        }
+        writer.write_all(&buf[..n]).await?;
+        counter.fetch_add(n as u64, Ordering::Relaxed);
+    }
+}
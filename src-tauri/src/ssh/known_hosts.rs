@@ -0,0 +1,376 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use base64::{Engine as _, engine::general_purpose};
+use ring::{digest, hmac};
+use russh_keys::key::PublicKey;
+use russh_keys::PublicKeyBase64;
+use serde::Serialize;
+
+/// A single host key entry as stored in known_hosts
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct KnownHostEntry {
+    /// 1-based line number in the file
+    pub line: usize,
+    /// Host patterns as written, or None for hashed entries
+    pub hosts: Option<String>,
+    pub hashed: bool,
+    pub marker: Option<String>,
+    pub key_type: String,
+    pub fingerprint: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HostKeyStatus {
+    Trusted,
+    Unknown,
+    /// The host is known, but with a different key
+    Changed { line: usize },
+    /// The presented key is explicitly marked @revoked
+    Revoked { line: usize },
+}
+
+struct Line<'a> {
+    marker: Option<&'a str>,
+    hosts: &'a str,
+    key_type: &'a str,
+    key_base64: &'a str,
+}
+
+/// Location of the user's OpenSSH known_hosts file
+pub fn default_path() -> PathBuf {
+    PathBuf::from(shellexpand::tilde("~/.ssh/known_hosts").to_string())
+}
+
+/// Host name as OpenSSH writes it: plain for port 22, `[host]:port` otherwise
+pub fn host_pattern(host: &str, port: u16) -> String {
+    let host = host.to_lowercase();
+    if port == 22 {
+        host
+    } else {
+        format!("[{}]:{}", host, port)
+    }
+}
+
+/// OpenSSH-style SHA256 fingerprint of a base64-encoded key blob
+pub fn fingerprint_base64(key_base64: &str) -> Option<String> {
+    let blob = general_purpose::STANDARD.decode(key_base64).ok()?;
+    let hash = digest::digest(&digest::SHA256, &blob);
+    Some(format!("SHA256:{}", general_purpose::STANDARD_NO_PAD.encode(hash.as_ref())))
+}
+
+pub fn fingerprint(key: &PublicKey) -> String {
+    fingerprint_base64(&key.public_key_base64()).unwrap_or_default()
+}
+
+/// Check a server key against the known_hosts file
+pub fn check(path: &Path, host: &str, port: u16, key: &PublicKey) -> io::Result<HostKeyStatus> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(HostKeyStatus::Unknown),
+        Err(e) => return Err(e),
+    };
+    let key_base64 = key.public_key_base64();
+    let key_type = blob_key_type(&key_base64).unwrap_or_default();
+    Ok(check_contents(&contents, &host_pattern(host, port), &key_type, &key_base64))
+}
+
+/// Only a line with the same key type can make the key `Changed`: a host
+/// known by its ECDSA key that now offers Ed25519 is unknown, as in OpenSSH
+fn check_contents(contents: &str, pattern: &str, key_type: &str, key_base64: &str) -> HostKeyStatus {
+    let mut trusted = false;
+    let mut changed = None;
+
+    for (index, raw) in contents.lines().enumerate() {
+        let Some(line) = parse_line(raw) else { continue };
+        if !hosts_match(line.hosts, pattern) {
+            continue;
+        }
+        let same_key = line.key_base64 == key_base64;
+        match line.marker {
+            Some("@revoked") if same_key => return HostKeyStatus::Revoked { line: index + 1 },
+            Some(_) => {}
+            None if same_key => trusted = true,
+            None if line.key_type == key_type => {
+                changed.get_or_insert(index + 1);
+            }
+            None => {}
+        }
+    }
+
+    if trusted {
+        HostKeyStatus::Trusted
+    } else if let Some(line) = changed {
+        HostKeyStatus::Changed { line }
+    } else {
+        HostKeyStatus::Unknown
+    }
+}
+
+/// Append a trusted key for host:port to the known_hosts file
+pub fn learn(path: &Path, host: &str, port: u16, key: &PublicKey) -> io::Result<()> {
+    let key_base64 = key.public_key_base64();
+    let key_type = blob_key_type(&key_base64)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Malformed public key"))?;
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    // Make sure we don't glue our entry onto an unterminated last line
+    let needs_newline = fs::read(path)
+        .map(|bytes| bytes.last().is_some_and(|b| *b != b'\n'))
+        .unwrap_or(false);
+
+    let mut file = fs::OpenOptions::new().create(true).append(true).open(path)?;
+    if needs_newline {
+        writeln!(file)?;
+    }
+    writeln!(file, "{} {} {}", host_pattern(host, port), key_type, key_base64)
+}
+
+/// List all host key entries, skipping comments and malformed lines
+pub fn list(path: &Path) -> io::Result<Vec<KnownHostEntry>> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+
+    Ok(contents
+        .lines()
+        .enumerate()
+        .filter_map(|(index, raw)| {
+            let line = parse_line(raw)?;
+            let hashed = line.hosts.starts_with("|1|");
+            Some(KnownHostEntry {
+                line: index + 1,
+                hosts: if hashed { None } else { Some(line.hosts.to_string()) },
+                hashed,
+                marker: line.marker.map(str::to_string),
+                key_type: line.key_type.to_string(),
+                fingerprint: fingerprint_base64(line.key_base64)?,
+            })
+        })
+        .collect())
+}
+
+/// Remove entries for host:port, optionally only the one with the given fingerprint.
+/// Returns the number of removed lines.
+pub fn revoke(path: &Path, host: &str, port: u16, fingerprint: Option<&str>) -> io::Result<usize> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e),
+    };
+
+    let pattern = host_pattern(host, port);
+    let mut removed = 0;
+    let mut kept = String::with_capacity(contents.len());
+
+    for raw in contents.lines() {
+        let remove = parse_line(raw).is_some_and(|line| {
+            hosts_match(line.hosts, &pattern)
+                && (fingerprint.is_none() || fingerprint_base64(line.key_base64).as_deref() == fingerprint)
+        });
+        if remove {
+            removed += 1;
+        } else {
+            kept.push_str(raw);
+            kept.push('\n');
+        }
+    }
+
+    if removed > 0 {
+        fs::write(path, kept)?;
+    }
+    Ok(removed)
+}
+
+fn parse_line(raw: &str) -> Option<Line<'_>> {
+    let raw = raw.trim();
+    if raw.is_empty() || raw.starts_with('#') {
+        return None;
+    }
+
+    let mut fields = raw.split_whitespace();
+    let mut first = fields.next()?;
+    let marker = if first.starts_with('@') {
+        let marker = first;
+        first = fields.next()?;
+        Some(marker)
+    } else {
+        None
+    };
+
+    Some(Line {
+        marker,
+        hosts: first,
+        key_type: fields.next()?,
+        key_base64: fields.next()?,
+    })
+}
+
+fn hosts_match(hosts: &str, pattern: &str) -> bool {
+    if let Some(hashed) = hosts.strip_prefix("|1|") {
+        return hashed_match(hashed, pattern);
+    }
+
+    let mut matched = false;
+    for candidate in hosts.split(',') {
+        if let Some(negated) = candidate.strip_prefix('!') {
+            if wildcard_match(&negated.to_lowercase(), pattern) {
+                return false;
+            }
+        } else if wildcard_match(&candidate.to_lowercase(), pattern) {
+            matched = true;
+        }
+    }
+    matched
+}
+
+/// Hashed entries are `|1|base64(salt)|base64(HMAC-SHA1(salt, host))`
+fn hashed_match(hashed: &str, pattern: &str) -> bool {
+    let Some((salt, hash)) = hashed.split_once('|') else { return false };
+    let (Ok(salt), Ok(hash)) = (
+        general_purpose::STANDARD.decode(salt),
+        general_purpose::STANDARD.decode(hash),
+    ) else {
+        return false;
+    };
+
+    let key = hmac::Key::new(hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY, &salt);
+    hmac::verify(&key, pattern.as_bytes(), &hash).is_ok()
+}
+
+/// Glob match supporting `*` and `?`, as used in known_hosts host patterns
+fn wildcard_match(glob: &str, text: &str) -> bool {
+    let glob: Vec<char> = glob.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut g, mut t) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        if g < glob.len() && (glob[g] == '?' || glob[g] == text[t]) {
+            g += 1;
+            t += 1;
+        } else if g < glob.len() && glob[g] == '*' {
+            backtrack = Some((g, t));
+            g += 1;
+        } else if let Some((star, matched)) = backtrack {
+            g = star + 1;
+            t = matched + 1;
+            backtrack = Some((star, matched + 1));
+        } else {
+            return false;
+        }
+    }
+
+    glob[g..].iter().all(|c| *c == '*')
+}
+
+/// Key type is the first string inside the wire-format key blob
+fn blob_key_type(key_base64: &str) -> Option<String> {
+    let blob = general_purpose::STANDARD.decode(key_base64).ok()?;
+    let len = u32::from_be_bytes(blob.get(..4)?.try_into().ok()?) as usize;
+    String::from_utf8(blob.get(4..4 + len)?.to_vec()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // "ssh-ed25519" key blob with an all-zero public key
+    const KEY_A: &str = "AAAAC3NzaC1lZDI1NTE5AAAAIAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA";
+    const KEY_B: &str = "AAAAC3NzaC1lZDI1NTE5AAAAIAEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEB";
+    const ED25519: &str = "ssh-ed25519";
+
+    fn hashed_host(salt: &[u8], pattern: &str) -> String {
+        let key = hmac::Key::new(hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY, salt);
+        let tag = hmac::sign(&key, pattern.as_bytes());
+        format!(
+            "|1|{}|{}",
+            general_purpose::STANDARD.encode(salt),
+            general_purpose::STANDARD.encode(tag.as_ref())
+        )
+    }
+
+    #[test]
+    fn test_plain_and_port_entries() {
+        let contents = format!(
+            "# comment\nexample.com,10.0.0.1 ssh-ed25519 {}\n[example.com]:2222 ssh-ed25519 {}\n",
+            KEY_A, KEY_B
+        );
+
+        assert_eq!(check_contents(&contents, &host_pattern("Example.com", 22), ED25519, KEY_A), HostKeyStatus::Trusted);
+        assert_eq!(check_contents(&contents, &host_pattern("10.0.0.1", 22), ED25519, KEY_A), HostKeyStatus::Trusted);
+        assert_eq!(check_contents(&contents, &host_pattern("example.com", 2222), ED25519, KEY_B), HostKeyStatus::Trusted);
+        assert_eq!(
+            check_contents(&contents, &host_pattern("example.com", 2222), ED25519, KEY_A),
+            HostKeyStatus::Changed { line: 3 }
+        );
+        assert_eq!(check_contents(&contents, &host_pattern("other.com", 22), ED25519, KEY_A), HostKeyStatus::Unknown);
+    }
+
+    #[test]
+    fn test_other_key_type_is_unknown_not_changed() {
+        // "ecdsa-sha2-nistp256" blob prefix; the rest doesn't matter here
+        let contents = "example.com ecdsa-sha2-nistp256 AAAAE2VjZHNhLXNoYTItbmlzdHAyNTYAAAAIbmlzdHAyNTYAAABBBA==\n";
+
+        assert_eq!(check_contents(contents, "example.com", ED25519, KEY_A), HostKeyStatus::Unknown);
+
+        let contents = format!("{}example.com ssh-ed25519 {}\n", contents, KEY_B);
+        assert_eq!(check_contents(&contents, "example.com", ED25519, KEY_A), HostKeyStatus::Changed { line: 2 });
+    }
+
+    #[test]
+    fn test_hashed_entries() {
+        let contents = format!("{} ssh-ed25519 {}\n", hashed_host(b"saltsaltsaltsaltsalt", "[bastion]:2200"), KEY_A);
+
+        assert_eq!(check_contents(&contents, &host_pattern("bastion", 2200), ED25519, KEY_A), HostKeyStatus::Trusted);
+        assert_eq!(check_contents(&contents, &host_pattern("bastion", 22), ED25519, KEY_A), HostKeyStatus::Unknown);
+    }
+
+    #[test]
+    fn test_wildcards_negation_and_revoked() {
+        let contents = format!(
+            "*.internal,!db.internal ssh-ed25519 {}\n@revoked * ssh-ed25519 {}\n",
+            KEY_A, KEY_B
+        );
+
+        assert_eq!(check_contents(&contents, "web.internal", ED25519, KEY_A), HostKeyStatus::Trusted);
+        assert_eq!(check_contents(&contents, "db.internal", ED25519, KEY_A), HostKeyStatus::Unknown);
+        assert_eq!(check_contents(&contents, "web.internal", ED25519, KEY_B), HostKeyStatus::Revoked { line: 2 });
+    }
+
+    #[test]
+    fn test_list_and_revoke() {
+        let path = std::env::temp_dir().join(format!("ggterm_known_hosts_{}", uuid::Uuid::new_v4()));
+        fs::write(
+            &path,
+            format!(
+                "example.com ssh-ed25519 {}\n{} ssh-ed25519 {}\nexample.com ssh-ed25519 {}",
+                KEY_A,
+                hashed_host(b"0123456789abcdefghij", "example.com"),
+                KEY_A,
+                KEY_B
+            ),
+        )
+        .unwrap();
+
+        let entries = list(&path).unwrap();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].hosts.as_deref(), Some("example.com"));
+        assert!(entries[1].hashed);
+        assert_eq!(entries[1].key_type, "ssh-ed25519");
+        assert_eq!(blob_key_type(KEY_A).as_deref(), Some("ssh-ed25519"));
+
+        let fingerprint_b = fingerprint_base64(KEY_B).unwrap();
+        assert_eq!(revoke(&path, "example.com", 22, Some(&fingerprint_b)).unwrap(), 1);
+        assert_eq!(revoke(&path, "example.com", 22, None).unwrap(), 2);
+        assert!(list(&path).unwrap().is_empty());
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,57 @@
+use serde::Serialize;
+use tauri::{AppHandle, Emitter};
+use crate::db::AuthMethod;
+use super::{EndReason, SSHError};
+
+/// A step in bringing up (or tearing down) a tab's connection, sent to the
+/// UI as `connection-lifecycle` events in order. Jump hosts go through the
+/// same steps before the final host, told apart by `host`.
+#[derive(Debug, Serialize)]
+#[serde(tag = "stage", rename_all = "snake_case")]
+pub enum Stage<'a> {
+    Resolving { host: &'a str, port: u16 },
+    Connecting { host: &'a str, address: String },
+    Handshaking { host: &'a str },
+    Authenticating { host: &'a str, method: AuthMethod },
+    OpeningChannel,
+    Ready { restored: bool },
+    Closed { reason: EndReason, exit_status: Option<u32>, exit_signal: Option<&'a str> },
+    Failed { error: &'a SSHError },
+}
+
+pub fn emit(app_handle: &AppHandle, tab_id: &str, stage: Stage<'_>) {
+    let mut payload = serde_json::to_value(&stage).unwrap_or_default();
+    if let Some(fields) = payload.as_object_mut() {
+        fields.insert("tab_id".to_string(), tab_id.into());
+    }
+    let _ = app_handle.emit("connection-lifecycle", payload);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stage_payloads() {
+        let stage = Stage::Authenticating { host: "db1", method: AuthMethod::KeyboardInteractive };
+        assert_eq!(
+            serde_json::to_value(&stage).unwrap(),
+            serde_json::json!({ "stage": "authenticating", "host": "db1", "method": "keyboard-interactive" })
+        );
+
+        let stage = Stage::Closed { reason: EndReason::Closed, exit_status: None, exit_signal: Some("KILL") };
+        assert_eq!(
+            serde_json::to_value(&stage).unwrap(),
+            serde_json::json!({ "stage": "closed", "reason": "closed", "exit_status": null, "exit_signal": "KILL" })
+        );
+    }
+
+    #[test]
+    fn test_failed_carries_error_code() {
+        let error = SSHError::HostKeyRejected("[db1]:2222".to_string());
+        let value = serde_json::to_value(&Stage::Failed { error: &error }).unwrap();
+        assert_eq!(value["stage"], "failed");
+        assert_eq!(value["error"]["code"], "host_key_rejected");
+        assert_eq!(value["error"]["message"], "Host key for [db1]:2222 was not accepted");
+    }
+}
@@ -0,0 +1,76 @@
+use std::time::Duration;
+use regex::Regex;
+use tauri::{AppHandle, Emitter};
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+use crate::db::LoginStep;
+use super::expect::{self, ExpectBuffer};
+use super::{send_input, SSHError};
+
+/// How long an `Expect` step waits when it doesn't say
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A running login script. It belongs to one shell: dropping it when the
+/// shell ends stops it from typing into whatever shell comes next.
+pub struct LoginScript(JoinHandle<()>);
+
+impl Drop for LoginScript {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// Run the session's login script against the freshly opened shell.
+/// `output` must be subscribed before the shell starts, so the first
+/// prompt isn't missed.
+pub fn spawn(
+    app_handle: AppHandle,
+    tab_id: String,
+    steps: Vec<LoginStep>,
+    mut output: broadcast::Receiver<Vec<u8>>,
+) -> LoginScript {
+    LoginScript(tokio::spawn(async move {
+        let mut buffer = ExpectBuffer::default();
+        for (index, step) in steps.iter().enumerate() {
+            if let Err(e) = run_step(&tab_id, step, &mut output, &mut buffer).await {
+                // The rest of the script likely depends on this step, so stop here
+                let _ = app_handle.emit("login-script-error", serde_json::json!({
+                    "tab_id": tab_id,
+                    "step": index,
+                    "error": e,
+                }));
+                return;
+            }
+        }
+    }))
+}
+
+async fn run_step(
+    tab_id: &str,
+    step: &LoginStep,
+    output: &mut broadcast::Receiver<Vec<u8>>,
+    buffer: &mut ExpectBuffer,
+) -> Result<(), SSHError> {
+    match step {
+        LoginStep::Command { command } => send_line(tab_id, command).await,
+        LoginStep::Expect { pattern, send, timeout_ms } => {
+            let pattern = Regex::new(pattern)
+                .map_err(|e| SSHError::LoginScript(format!("Invalid pattern: {}", e)))?;
+            let timeout = timeout_ms.map(Duration::from_millis).unwrap_or(DEFAULT_TIMEOUT);
+
+            match expect::wait_for(output, buffer, std::slice::from_ref(&pattern), timeout).await? {
+                Some(_) if send.is_empty() => Ok(()),
+                Some(_) => send_line(tab_id, send).await,
+                None => Err(SSHError::LoginScript(format!(
+                    "Timed out after {:?} waiting for /{}/",
+                    timeout, pattern
+                ))),
+            }
+        }
+    }
+}
+
+/// Type a line as if the user pressed Enter after it
+async fn send_line(tab_id: &str, line: &str) -> Result<(), SSHError> {
+    send_input(tab_id, &format!("{}\r", line)).await
+}
@@ -1,13 +1,45 @@
 use std::collections::HashMap;
-use std::sync::Arc;
 use tauri::{AppHandle, Emitter};
-use crate::db::SSHSession;
+use crate::db::{ForwardKind, PortForward, SSHSession};
+use crate::db::async_db::DbError;
+use sqlx::SqlitePool;
 use russh::*;
 use russh::client;
 use russh_keys::*;
-use tokio::sync::{mpsc, RwLock};
+use tokio::sync::{broadcast, mpsc, oneshot, RwLock};
 use async_trait::async_trait;
 use std::io::Cursor;
+use std::sync::Arc;
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+
+pub mod agent;
+pub mod auth;
+pub mod connection;
+pub mod exec;
+pub mod expect;
+pub mod forward;
+pub mod keys;
+pub mod known_hosts;
+pub mod lifecycle;
+pub mod login;
+pub mod output;
+pub mod pool;
+pub mod prompt;
+pub mod pty;
+pub mod reconnect;
+pub mod socks;
+pub mod throttle;
+pub mod x11;
+
+use auth::AuthContext;
+use connection::Connection;
+use forward::{ForwardStatus, RemoteRoutes};
+use known_hosts::HostKeyStatus;
+use lifecycle::Stage;
+use output::{OutputMode, TerminalOutput};
+use reconnect::Backoff;
+use x11::X11Forwarding;
 
 #[derive(Debug, thiserror::Error)]
 pub enum SSHError {
@@ -19,119 +51,742 @@ pub enum SSHError {
     Io(#[from] std::io::Error),
     #[error("Connection failed: {0}")]
     ConnectionFailed(String),
+    #[error("Could not resolve {0}")]
+    Resolve(String),
+    #[error("Authentication failed (tried {0})")]
+    AuthFailed(String),
+    #[error("Host key for {host} does not match known_hosts line {line} (server sent {fingerprint}). Possible man-in-the-middle attack!")]
+    HostKeyMismatch { host: String, fingerprint: String, line: usize },
+    #[error("Host key for {host} ({fingerprint}) is revoked in known_hosts")]
+    HostKeyRevoked { host: String, fingerprint: String },
+    #[error("Host key for {0} was not accepted")]
+    HostKeyRejected(String),
+    #[error("Prompt cancelled: {0}")]
+    PromptCancelled(String),
+    #[error("Not supported: {0}")]
+    Unsupported(String),
+    #[error("SSH agent error: {0}")]
+    Agent(String),
+    #[error("Wrong passphrase for private key {0}")]
+    WrongPassphrase(String),
+    #[error("Database error: {0}")]
+    Db(#[from] DbError),
+    #[error("Port forward error: {0}")]
+    Forward(String),
+    #[error("Login script: {0}")]
+    LoginScript(String),
+    #[error("Jump host {hop} ({name}) failed: {source}")]
+    JumpHost { hop: usize, name: String, source: Box<SSHError> },
+}
+
+impl SSHError {
+    /// Stable identifier the UI maps to a helpful message; unlike the
+    /// `Display` text it doesn't change between versions
+    pub fn code(&self) -> &'static str {
+        match self {
+            SSHError::Russh(russh::Error::Disconnect) | SSHError::Russh(russh::Error::HUP) => "disconnected",
+            SSHError::Russh(russh::Error::ConnectionTimeout) => "timeout",
+            SSHError::Russh(russh::Error::NotAuthenticated) => "auth_failed",
+            SSHError::Russh(_) => "protocol",
+            SSHError::Key(_) => "invalid_key",
+            SSHError::Io(e) => io_code(e),
+            SSHError::ConnectionFailed(_) => "connection_failed",
+            SSHError::Resolve(_) => "dns",
+            SSHError::AuthFailed(_) => "auth_failed",
+            SSHError::HostKeyMismatch { .. } => "host_key_mismatch",
+            SSHError::HostKeyRevoked { .. } => "host_key_revoked",
+            SSHError::HostKeyRejected(_) => "host_key_rejected",
+            SSHError::PromptCancelled(_) => "cancelled",
+            SSHError::Unsupported(_) => "unsupported",
+            SSHError::Agent(_) => "agent",
+            SSHError::WrongPassphrase(_) => "wrong_passphrase",
+            SSHError::Db(_) => "database",
+            SSHError::Forward(_) => "port_forward",
+            SSHError::LoginScript(_) => "login_script",
+            SSHError::JumpHost { .. } => "jump_host",
+        }
+    }
+}
+
+/// Error code for an IO error, shared with the SFTP errors
+pub fn io_code(error: &std::io::Error) -> &'static str {
+    match error.kind() {
+        std::io::ErrorKind::ConnectionRefused => "connection_refused",
+        std::io::ErrorKind::TimedOut => "timeout",
+        std::io::ErrorKind::ConnectionReset | std::io::ErrorKind::ConnectionAborted => "connection_reset",
+        std::io::ErrorKind::NotFound => "not_found",
+        std::io::ErrorKind::PermissionDenied => "permission_denied",
+        _ => "io",
+    }
+}
+
+/// `{ code, message }`, plus `hop` and the underlying `cause` for jump host errors
+impl Serialize for SSHError {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeMap;
+
+        let mut map = serializer.serialize_map(None)?;
+        map.serialize_entry("code", self.code())?;
+        map.serialize_entry("message", &self.to_string())?;
+        if let SSHError::JumpHost { hop, source, .. } = self {
+            map.serialize_entry("hop", hop)?;
+            map.serialize_entry("cause", source)?;
+        }
+        map.end()
+    }
+}
+
+/// Terminal dimensions in character cells and pixels
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PtySize {
+    pub cols: u32,
+    pub rows: u32,
+    #[serde(default)]
+    pub pixel_w: u32,
+    #[serde(default)]
+    pub pixel_h: u32,
+}
+
+impl Default for PtySize {
+    fn default() -> Self {
+        Self { cols: 80, rows: 24, pixel_w: 0, pixel_h: 0 }
+    }
+}
+
+/// Requests sent from the UI to a live session task. There is no break:
+/// russh 0.42 can't send the RFC 4335 `break` channel request.
+pub enum SessionCommand {
+    Input(Vec<u8>),
+    Resize(PtySize),
+    Signal(Sig),
+    /// Exit status reported by the remote shell so far, if any
+    ExitStatus(oneshot::Sender<Option<u32>>),
+    SetOutputMode(OutputMode),
+    /// The renderer has drawn this many bytes of output
+    Ack(usize),
+    /// Share the tab's authenticated connection, e.g. to open an SFTP channel on it
+    Handle(oneshot::Sender<Arc<client::Handle<Client>>>),
+    /// The pooled connection the tab's shell runs on
+    Connection(oneshot::Sender<Arc<Connection>>),
+    Close,
 }
 
 // Improved: Use RwLock instead of Mutex for better concurrent read performance
 // Multiple tabs can read simultaneously without blocking each other
 lazy_static::lazy_static! {
-    static ref ACTIVE_SESSIONS: RwLock<HashMap<String, mpsc::Sender<Vec<u8>>>> = RwLock::new(HashMap::new());
+    static ref ACTIVE_SESSIONS: RwLock<HashMap<String, ActiveSession>> = RwLock::new(HashMap::new());
+}
+
+lazy_static::lazy_static! {
+    /// How each tab's last session ended, kept after the session task is gone
+    static ref EXITED_TABS: RwLock<HashMap<String, TabExit>> = RwLock::new(HashMap::new());
 }
 
-struct Client;
+/// A connected tab
+struct ActiveSession {
+    /// The saved session the tab was opened from
+    session_id: String,
+    tx: mpsc::Sender<SessionCommand>,
+    /// The same sender as the shell's `output_tap`, for subscribing from outside
+    output: broadcast::Sender<Vec<u8>>,
+}
+
+/// russh event handler for one connection
+pub struct Client {
+    app_handle: AppHandle,
+    tab_id: String,
+    host: String,
+    port: u16,
+    remote_routes: RemoteRoutes,
+    /// The session asked for agent forwarding, so agent channels are expected
+    forward_agent: bool,
+    /// Set when the session forwards X11
+    x11: Option<Arc<X11Forwarding>>,
+}
 
 #[async_trait]
 impl client::Handler for Client {
     type Error = SSHError;
 
     async fn check_server_key(
-        self,
-        _server_public_key: &key::PublicKey,
-    ) -> Result<(Self, bool), Self::Error> {
-        Ok((self, true)) // In a real app, you should check the key
+        &mut self,
+        server_public_key: &key::PublicKey,
+    ) -> Result<bool, Self::Error> {
+        let path = known_hosts::default_path();
+        let host = known_hosts::host_pattern(&self.host, self.port);
+        let fingerprint = known_hosts::fingerprint(server_public_key);
+
+        match known_hosts::check(&path, &self.host, self.port, server_public_key)? {
+            HostKeyStatus::Trusted => Ok(true),
+            HostKeyStatus::Changed { line } => Err(SSHError::HostKeyMismatch { host, fingerprint, line }),
+            HostKeyStatus::Revoked { .. } => Err(SSHError::HostKeyRevoked { host, fingerprint }),
+            HostKeyStatus::Unknown => {
+                // Trust on first use: ask the user before remembering the key
+                let accepted: bool = prompt::ask(&self.app_handle, "host-key-prompt", serde_json::json!({
+                    "tab_id": self.tab_id,
+                    "host": self.host,
+                    "port": self.port,
+                    "key_type": server_public_key.name(),
+                    "fingerprint": fingerprint,
+                })).await?;
+
+                if !accepted {
+                    return Err(SSHError::HostKeyRejected(host));
+                }
+                known_hosts::learn(&path, &self.host, self.port, server_public_key)?;
+                Ok(true)
+            }
+        }
+    }
+
+    async fn server_channel_open_forwarded_tcpip(
+        &mut self,
+        channel: Channel<client::Msg>,
+        _connected_address: &str,
+        connected_port: u32,
+        originator_address: &str,
+        originator_port: u32,
+        _session: &mut client::Session,
+    ) -> Result<(), Self::Error> {
+        let originator = format!("{}:{}", originator_address, originator_port);
+        forward::route_remote(&self.remote_routes, channel, connected_port, originator);
+        Ok(())
+    }
+
+    async fn server_channel_open_x11(
+        &mut self,
+        channel: Channel<client::Msg>,
+        originator_address: &str,
+        originator_port: u32,
+        _session: &mut client::Session,
+    ) -> Result<(), Self::Error> {
+        let originator = format!("{}:{}", originator_address, originator_port);
+        match &self.x11 {
+            Some(x11) => x11::serve(x11.clone(), channel, originator),
+            None => eprintln!("{}:{} opened an X11 channel without X11 forwarding, refusing", self.host, self.port),
+        }
+        Ok(())
+    }
+
+    async fn server_channel_open_agent_forward(
+        &mut self,
+        channel: Channel<client::Msg>,
+        _session: &mut client::Session,
+    ) -> Result<(), Self::Error> {
+        if self.forward_agent {
+            agent::serve_forwarded(channel, format!("{}:{}", self.host, self.port));
+        } else {
+            // A server shouldn't ask without our request; dropping the channel refuses it
+            eprintln!("{}:{} opened an agent channel without agent forwarding, refusing", self.host, self.port);
+        }
+        Ok(())
     }
 }
 
+/// Why a tab's shell ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum EndReason {
+    /// The remote shell exited or closed the channel
+    Closed,
+    /// The tab was closed from the UI
+    ClosedByUser,
+    /// The transport died under the shell, e.g. keepalives went unanswered
+    ConnectionLost,
+    /// Connecting failed, or the session ended with an error
+    Failed,
+}
+
+/// How a tab's remote shell ended, as reported by the server
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ShellExit {
+    pub exit_status: Option<u32>,
+    /// Signal that killed the shell, e.g. "HUP"
+    pub exit_signal: Option<String>,
+    pub core_dumped: bool,
+    /// Text the server sent along with the signal
+    pub error_message: Option<String>,
+}
+
+/// What is kept about a tab after its session ended, until it reconnects
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TabExit {
+    #[serde(flatten)]
+    pub shell: ShellExit,
+    pub reason: EndReason,
+    pub error: Option<String>,
+    pub error_code: Option<&'static str>,
+    pub ended_at: String,
+}
+
+/// Written into the terminal when the connection drops and a reconnect is on
+const LOST_NOTICE: &[u8] = b"\r\n\x1b[33m[Connection lost, reconnecting...]\x1b[0m\r\n";
+
+/// Output chunks buffered for a slow script before it starts missing some
+const OUTPUT_TAP_CAPACITY: usize = 1024;
+
+/// What a tab's shell carries over to a reconnect
+struct ShellState {
+    /// Last size the UI asked for, to reopen the PTY with
+    size: PtySize,
+    /// A shell was opened on the current attempt
+    opened: bool,
+    /// The current attempt is a reconnect
+    restored: bool,
+    /// Reported by the remote shell of the current attempt
+    exit: ShellExit,
+    /// Copies of the shell's output for scripts watching for a prompt
+    output_tap: broadcast::Sender<Vec<u8>>,
+}
+
 pub async fn connect(
+    pool: SqlitePool,
     config: &SSHSession,
     app_handle: AppHandle,
     tab_id: String,
+    size: PtySize,
+    mut output: TerminalOutput,
 ) -> Result<(), SSHError> {
     // Increased buffer from 100 to 10000 to handle high-throughput SSH sessions
     // This prevents data loss when commands produce rapid output
     let (tx, mut rx) = mpsc::channel(10000);
-    ACTIVE_SESSIONS.write().await.insert(tab_id.clone(), tx);
+    let output_tap = broadcast::channel(OUTPUT_TAP_CAPACITY).0;
+    ACTIVE_SESSIONS.write().await.insert(
+        tab_id.clone(),
+        ActiveSession { session_id: config.id.clone(), tx, output: output_tap.clone() },
+    );
+    EXITED_TABS.write().await.remove(&tab_id);
 
-    let client_config = Arc::new(client::Config::default());
-    let client_handler = Client;
+    let mut shell = ShellState { size, opened: false, restored: false, exit: ShellExit::default(), output_tap };
+    let mut result = run_session(&pool, config, &app_handle, &tab_id, &mut shell, &mut output, &mut rx).await;
 
-    let addr = format!("{}:{}", config.host, config.port);
-    let mut session = client::connect(client_config, addr, client_handler).await?;
+    if config.keepalive.auto_reconnect && connection_lost(&result, &shell) {
+        output.push(LOST_NOTICE);
+        output.flush();
 
-    let auth_result = if config.auth_type == "password" {
-        session.authenticate_password(&config.username, config.password.as_deref().unwrap_or("")).await?
-    } else {
-        let key_path = config.private_key.as_deref().unwrap_or("");
-        let expanded_path = shellexpand::tilde(key_path).to_string();
-        let key_pair = load_secret_key(expanded_path, None)?;
-        session.authenticate_publickey(&config.username, Arc::new(key_pair)).await?
+        let mut backoff = Backoff::new(config.keepalive.max_reconnect_attempts);
+        while let Some(delay) = backoff.next_delay() {
+            let _ = app_handle.emit("connection-status", serde_json::json!({
+                "tab_id": tab_id,
+                "connected": false,
+                "reason": EndReason::ConnectionLost,
+                "reconnecting": true,
+                "attempt": backoff.attempt(),
+                "retry_in_ms": delay.as_millis() as u64,
+            }));
+            if !wait_to_reconnect(delay, &mut shell, &mut output, &mut rx).await {
+                result = Ok(EndReason::ClosedByUser);
+                break;
+            }
+
+            shell.restored = true;
+            result = run_session(&pool, config, &app_handle, &tab_id, &mut shell, &mut output, &mut rx).await;
+            if connection_lost(&result, &shell) {
+                // Lost again after coming back: start over with every attempt
+                backoff.reset();
+                output.push(LOST_NOTICE);
+                output.flush();
+            } else if !matches!(&result, Err(e) if is_transient(e)) {
+                break;
+            }
+        }
+    }
+
+    ACTIVE_SESSIONS.write().await.remove(&tab_id);
+    crate::sftp::forget(&tab_id).await;
+    let reason = match &result {
+        Ok(reason) => *reason,
+        Err(_) if shell.opened || shell.restored => EndReason::ConnectionLost,
+        Err(_) => EndReason::Failed,
     };
+    let exit = &shell.exit;
+    match &result {
+        Ok(_) => lifecycle::emit(&app_handle, &tab_id, Stage::Closed {
+            reason,
+            exit_status: exit.exit_status,
+            exit_signal: exit.exit_signal.as_deref(),
+        }),
+        Err(e) => lifecycle::emit(&app_handle, &tab_id, Stage::Failed { error: e }),
+    }
+    let error = result.as_ref().err();
+    let _ = app_handle.emit("connection-status", serde_json::json!({
+        "tab_id": tab_id,
+        "connected": false,
+        "reason": reason,
+        "exit_status": exit.exit_status,
+        "exit_signal": exit.exit_signal,
+        "core_dumped": exit.core_dumped,
+        "error": error.map(|e| e.to_string()),
+        "error_code": error.map(SSHError::code),
+    }));
 
-    if !auth_result {
-        return Err(SSHError::ConnectionFailed("Authentication failed".to_string()));
+    // Nobody asks how a tab the user closed ended
+    if reason != EndReason::ClosedByUser {
+        EXITED_TABS.write().await.insert(tab_id, TabExit {
+            shell: shell.exit,
+            reason,
+            error: error.map(|e| e.to_string()),
+            error_code: error.map(SSHError::code),
+            ended_at: chrono::Utc::now().to_rfc3339(),
+        });
     }
+    result.map(|_| ())
+}
 
-    let mut channel = session.channel_open_session().await?;
-    channel.request_pty(false, "xterm-256color", 80, 24, 0, 0, &[]).await?;
+/// An error after the shell was open means the transport failed under it
+fn connection_lost(result: &Result<EndReason, SSHError>, shell: &ShellState) -> bool {
+    match result {
+        Ok(reason) => *reason == EndReason::ConnectionLost,
+        Err(_) => shell.opened,
+    }
+}
+
+/// Whether a failed reconnect is worth trying again. Anything that needs
+/// the user (a changed host key, a cancelled prompt) is not.
+fn is_transient(error: &SSHError) -> bool {
+    !matches!(
+        error,
+        SSHError::HostKeyMismatch { .. }
+            | SSHError::HostKeyRevoked { .. }
+            | SSHError::HostKeyRejected(_)
+            | SSHError::PromptCancelled(_)
+            | SSHError::WrongPassphrase(_)
+    )
+}
+
+/// Sleep before a reconnect attempt while still serving the tab. Returns
+/// false if the tab was closed in the meantime.
+async fn wait_to_reconnect(
+    delay: Duration,
+    shell: &mut ShellState,
+    output: &mut TerminalOutput,
+    rx: &mut mpsc::Receiver<SessionCommand>,
+) -> bool {
+    let sleep = tokio::time::sleep(delay);
+    tokio::pin!(sleep);
+
+    loop {
+        tokio::select! {
+            _ = &mut sleep => return true,
+            command = rx.recv() => match command {
+                Some(SessionCommand::Resize(size)) => shell.size = size,
+                Some(SessionCommand::SetOutputMode(mode)) => output.set_mode(mode),
+                Some(SessionCommand::Ack(bytes)) => output.ack(bytes),
+                Some(SessionCommand::Close) | None => return false,
+                // There is no shell to take input, and dropping a reply
+                // tells its caller the tab isn't connected
+                Some(_) => {}
+            },
+        }
+    }
+}
+
+async fn run_session(
+    pool: &SqlitePool,
+    config: &SSHSession,
+    app_handle: &AppHandle,
+    tab_id: &str,
+    shell: &mut ShellState,
+    output: &mut TerminalOutput,
+    rx: &mut mpsc::Receiver<SessionCommand>,
+) -> Result<EndReason, SSHError> {
+    shell.opened = false;
+    shell.exit = ShellExit::default();
+    let lease = pool::acquire(&AuthContext { pool, app_handle, tab_id }, config).await?;
+    let connection = lease.connection.clone();
+
+    // Another tab may already be connected to this session, in which case
+    // this is just one more channel on its connection
+    let size = shell.size;
+    lifecycle::emit(app_handle, tab_id, Stage::OpeningChannel);
+    let mut channel = connection.handle.channel_open_session().await?;
+    let modes = pty::modes(config);
+    channel.request_pty(false, pty::term(config), size.cols, size.rows, size.pixel_w, size.pixel_h, &modes).await?;
+    if config.forward_agent {
+        channel.agent_forward(false).await?;
+    }
+    if let Some(x11) = &connection.x11 {
+        channel
+            .request_x11(false, false, x11::MIT_MAGIC_COOKIE, x11.fake_cookie_hex(), x11.screen())
+            .await?;
+    }
+    // No reply wanted: a server that doesn't accept a variable just ignores it
+    for (name, value) in pty::env(config) {
+        channel.set_env(false, name, value).await?;
+    }
+    // Subscribe before the shell starts so the script sees the first prompt
+    let _login_script = if config.login_script.is_empty() {
+        None
+    } else {
+        let output = shell.output_tap.subscribe();
+        Some(login::spawn(app_handle.clone(), tab_id.to_string(), config.login_script.clone(), output))
+    };
     channel.request_shell(false).await?;
+    shell.opened = true;
 
+    if shell.restored {
+        output.push(b"\x1b[32m[Session restored]\x1b[0m\r\n");
+    }
+    lifecycle::emit(app_handle, tab_id, Stage::Ready { restored: shell.restored });
     let _ = app_handle.emit("connection-status", serde_json::json!({
         "tab_id": tab_id,
         "connected": true,
+        "restored": shell.restored,
     }));
 
+    // Forwards bind ports, so only the first tab on a connection starts them
+    if let Some(remote_forwards) = lease.claim_forwards() {
+        let mut forwards = connection.forwards.lock().await;
+        let listening_forwards = config.port_forwards.iter().filter(|f| f.kind != ForwardKind::Remote && f.auto_start);
+        for forward in listening_forwards {
+            let result = forwards.start(forward.clone()).await;
+            report_forward_error(app_handle, tab_id, forward, result);
+        }
+        for (forward, requested) in remote_forwards {
+            let result = requested.and_then(|()| forwards.adopt_remote(forward.clone()));
+            report_forward_error(app_handle, tab_id, &forward, result);
+        }
+    }
+
+    // Not reading the channel alone doesn't slow the server down, see `throttle`
+    let mut read_hold = None;
+
     // Main loop for handling input/output
-    loop {
+    let reason = loop {
+        let flush_at = output.deadline();
+        if output.is_paused() != read_hold.is_some() {
+            read_hold = output.is_paused().then(|| connection.read_gate.hold());
+        }
+
         tokio::select! {
-            Some(data) = rx.recv() => {
-                // Use Cursor to wrap data as AsyncRead
-                let cursor = Cursor::new(data);
-                channel.data(cursor).await.map_err(|e| SSHError::Russh(e))?;
+            command = rx.recv() => {
+                match command {
+                    Some(SessionCommand::Input(data)) => {
+                        // Use Cursor to wrap data as AsyncRead
+                        let cursor = Cursor::new(data);
+                        channel.data(cursor).await.map_err(|e| SSHError::Russh(e))?;
+                    }
+                    Some(SessionCommand::Resize(size)) => {
+                        shell.size = size;
+                        channel.window_change(size.cols, size.rows, size.pixel_w, size.pixel_h).await?;
+                    }
+                    Some(SessionCommand::Signal(signal)) => {
+                        channel.signal(signal).await?;
+                    }
+                    Some(SessionCommand::ExitStatus(reply)) => {
+                        let _ = reply.send(shell.exit.exit_status);
+                    }
+                    Some(SessionCommand::SetOutputMode(mode)) => {
+                        output.set_mode(mode);
+                    }
+                    Some(SessionCommand::Ack(bytes)) => {
+                        output.ack(bytes);
+                    }
+                    Some(SessionCommand::Handle(reply)) => {
+                        let _ = reply.send(connection.handle.clone());
+                    }
+                    Some(SessionCommand::Connection(reply)) => {
+                        let _ = reply.send(connection.clone());
+                    }
+                    // Closed by the UI, or every sender is gone
+                    Some(SessionCommand::Close) | None => {
+                        let _ = channel.eof().await;
+                        let _ = channel.close().await;
+                        break EndReason::ClosedByUser;
+                    }
+                }
             }
-            result = channel.wait() => {
+            // Backpressure: stop taking data while the renderer catches up
+            result = channel.wait(), if !output.is_paused() => {
                 match result {
                     Some(msg) => {
                         match msg {
                             ChannelMsg::Data { ref data } => {
-                                // Data is handled by the Handler trait
-                                let data_str = String::from_utf8_lossy(data).to_string();
-                                let _ = app_handle.emit("terminal-data", serde_json::json!({
-                                    "tab_id": tab_id,
-                                    "data": data_str,
-                                }));
+                                output.push(data);
+                                if shell.output_tap.receiver_count() > 0 {
+                                    let _ = shell.output_tap.send(data.to_vec());
+                                }
+                            }
+                            ChannelMsg::ExitStatus { exit_status } => {
+                                shell.exit.exit_status = Some(exit_status);
                             }
-                            ChannelMsg::Eof => {
-                                break;
+                            ChannelMsg::ExitSignal { signal_name: signal, core_dumped, error_message, .. } => {
+                                shell.exit.exit_signal = Some(signal_name(&signal));
+                                shell.exit.core_dumped = core_dumped;
+                                shell.exit.error_message = Some(error_message).filter(|m| !m.is_empty());
                             }
+                            // The exit status usually follows EOF, so wait for Close
+                            ChannelMsg::Eof => {}
                             ChannelMsg::Close => {
-                                break;
+                                break EndReason::Closed;
                             }
                             _ => {}
                         }
                     }
-                    None => break,
+                    // The channel went away without a Close: the transport died
+                    None if connection.handle.is_closed() => break EndReason::ConnectionLost,
+                    None => break EndReason::Closed,
                 }
             }
+            _ = tokio::time::sleep_until(flush_at.unwrap_or_else(tokio::time::Instant::now)), if flush_at.is_some() => {
+                output.flush();
+            }
         }
-    }
+    };
 
-    let _ = app_handle.emit("connection-status", serde_json::json!({
-        "tab_id": tab_id,
-        "connected": false,
-    }));
-    close_connection(&tab_id).await;
-    Ok(())
+    output.flush();
+    // The connection itself, and its forwards, close once its last lease is gone
+    drop(lease);
+    Ok(reason)
+}
+
+/// A forward that can't start (e.g. its port is busy) shouldn't take the
+/// whole session down, so it is only reported
+fn report_forward_error<T>(app_handle: &AppHandle, tab_id: &str, forward: &PortForward, result: Result<T, SSHError>) {
+    if let Err(e) = result {
+        let _ = app_handle.emit("port-forward-error", serde_json::json!({
+            "tab_id": tab_id,
+            "forward_id": forward.id,
+            "error": e.to_string(),
+        }));
+    }
 }
 
 pub async fn send_input(tab_id: &str, data: &str) -> Result<(), SSHError> {
+    send_command(tab_id, SessionCommand::Input(data.as_bytes().to_vec())).await
+}
+
+pub async fn resize(tab_id: &str, size: PtySize) -> Result<(), SSHError> {
+    send_command(tab_id, SessionCommand::Resize(size)).await
+}
+
+pub async fn set_output_mode(tab_id: &str, mode: OutputMode) -> Result<(), SSHError> {
+    send_command(tab_id, SessionCommand::SetOutputMode(mode)).await
+}
+
+pub async fn ack_output(tab_id: &str, bytes: usize) -> Result<(), SSHError> {
+    send_command(tab_id, SessionCommand::Ack(bytes)).await
+}
+
+pub async fn send_signal(tab_id: &str, signal: &str) -> Result<(), SSHError> {
+    send_command(tab_id, SessionCommand::Signal(parse_signal(signal))).await
+}
+
+pub async fn exit_status(tab_id: &str) -> Result<Option<u32>, SSHError> {
+    if let Some(exit) = EXITED_TABS.read().await.get(tab_id) {
+        return Ok(exit.shell.exit_status);
+    }
+    let (reply, rx) = oneshot::channel();
+    send_command(tab_id, SessionCommand::ExitStatus(reply)).await?;
+    rx.await.map_err(|_| SSHError::ConnectionFailed("Channel closed".to_string()))
+}
+
+/// How the tab's last session ended, once it has
+pub async fn tab_exit(tab_id: &str) -> Option<TabExit> {
+    EXITED_TABS.read().await.get(tab_id).cloned()
+}
+
+// Forwards belong to the connection, so these act on the same forwards
+// from any tab that shares it
+
+pub async fn start_forward(tab_id: &str, forward: PortForward) -> Result<ForwardStatus, SSHError> {
+    tab_connection(tab_id).await?.forwards.lock().await.start(forward).await
+}
+
+pub async fn stop_forward(tab_id: &str, id: &str) -> Result<bool, SSHError> {
+    tab_connection(tab_id).await?.forwards.lock().await.stop(id).await
+}
+
+pub async fn list_forwards(tab_id: &str) -> Result<Vec<ForwardStatus>, SSHError> {
+    Ok(tab_connection(tab_id).await?.forwards.lock().await.list())
+}
+
+async fn tab_connection(tab_id: &str) -> Result<Arc<Connection>, SSHError> {
+    let (reply, rx) = oneshot::channel();
+    send_command(tab_id, SessionCommand::Connection(reply)).await?;
+    rx.await.map_err(|_| SSHError::ConnectionFailed(format!("Tab {} is not connected", tab_id)))
+}
+
+/// The live connection of a tab, for opening more channels on it
+pub async fn connection_handle(tab_id: &str) -> Result<Arc<client::Handle<Client>>, SSHError> {
+    let (reply, rx) = oneshot::channel();
+    send_command(tab_id, SessionCommand::Handle(reply)).await?;
+    rx.await.map_err(|_| SSHError::ConnectionFailed(format!("Tab {} is not connected", tab_id)))
+}
+
+/// The saved session a connected tab was opened from
+pub async fn tab_session_id(tab_id: &str) -> Option<String> {
+    ACTIVE_SESSIONS.read().await.get(tab_id).map(|active| active.session_id.clone())
+}
+
+/// Receive a copy of everything the tab's shell prints from now on,
+/// across reconnects, until the tab is closed
+pub async fn subscribe_output(tab_id: &str) -> Result<broadcast::Receiver<Vec<u8>>, SSHError> {
+    ACTIVE_SESSIONS
+        .read()
+        .await
+        .get(tab_id)
+        .map(|active| active.output.subscribe())
+        .ok_or_else(|| SSHError::ConnectionFailed(format!("Tab {} is not connected", tab_id)))
+}
+
+async fn send_command(tab_id: &str, command: SessionCommand) -> Result<(), SSHError> {
     // Use read lock for faster lookup - doesn't block other readers
-    let tx_opt = ACTIVE_SESSIONS.read().await.get(tab_id).cloned();
+    let tx_opt = ACTIVE_SESSIONS.read().await.get(tab_id).map(|active| active.tx.clone());
     if let Some(tx) = tx_opt {
-        tx.send(data.as_bytes().to_vec()).await.map_err(|_| SSHError::ConnectionFailed("Channel closed".to_string()))?;
+        tx.send(command).await.map_err(|_| SSHError::ConnectionFailed("Channel closed".to_string()))?;
     }
     Ok(())
 }
 
+/// Ask the session task to close its channel and release its connection
 pub async fn close_connection(tab_id: &str) {
     // Use write lock only when modifying
-    ACTIVE_SESSIONS.write().await.remove(tab_id);
-}
\ No newline at end of file
+    let active = ACTIVE_SESSIONS.write().await.remove(tab_id);
+    if let Some(active) = active {
+        let _ = active.tx.send(SessionCommand::Close).await;
+    }
+}
+
+/// Map a signal name like "INT" or "SIGINT" to an SSH signal
+fn parse_signal(name: &str) -> Sig {
+    let name = name.trim().trim_start_matches("SIG");
+    match name {
+        "ABRT" => Sig::ABRT,
+        "ALRM" => Sig::ALRM,
+        "FPE" => Sig::FPE,
+        "HUP" => Sig::HUP,
+        "ILL" => Sig::ILL,
+        "INT" => Sig::INT,
+        "KILL" => Sig::KILL,
+        "PIPE" => Sig::PIPE,
+        "QUIT" => Sig::QUIT,
+        "SEGV" => Sig::SEGV,
+        "TERM" => Sig::TERM,
+        "USR1" => Sig::USR1,
+        other => Sig::Custom(other.to_string()),
+    }
+}
+
+/// Name of an SSH signal without the SIG prefix, e.g. "INT"
+fn signal_name(signal: &Sig) -> String {
+    match signal {
+        Sig::ABRT => "ABRT",
+        Sig::ALRM => "ALRM",
+        Sig::FPE => "FPE",
+        Sig::HUP => "HUP",
+        Sig::ILL => "ILL",
+        Sig::INT => "INT",
+        Sig::KILL => "KILL",
+        Sig::PIPE => "PIPE",
+        Sig::QUIT => "QUIT",
+        Sig::SEGV => "SEGV",
+        Sig::TERM => "TERM",
+        Sig::USR1 => "USR1",
+        Sig::Custom(name) => name.as_str(),
+    }
+    .to_string()
+}
@@ -0,0 +1,289 @@
+use std::time::Duration;
+use base64::{Engine as _, engine::general_purpose};
+use serde::{Deserialize, Serialize};
+use tauri::ipc::Channel;
+use tokio::time::Instant;
+
+/// Coalesce output for at most this long before handing it to the renderer
+pub const BATCH_WINDOW: Duration = Duration::from_millis(8);
+/// Send a batch right away once this much output is buffered
+pub const MAX_BATCH_BYTES: usize = 64 * 1024;
+/// Stop reading from the server when this much output is unacknowledged
+pub const HIGH_WATERMARK: usize = 1024 * 1024;
+/// Resume reading once the renderer has caught up to this level
+pub const LOW_WATERMARK: usize = 256 * 1024;
+
+/// How terminal output is delivered to the frontend
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum OutputMode {
+    /// UTF-8 text, decoded incrementally across packets
+    #[default]
+    Text,
+    /// Raw bytes as a base64 string
+    Base64,
+    /// Raw bytes as a JSON array of numbers
+    Bytes,
+}
+
+/// Incremental UTF-8 decoder that carries incomplete sequences over to the next chunk
+#[derive(Debug, Default)]
+pub struct Utf8Decoder {
+    pending: Vec<u8>,
+}
+
+impl Utf8Decoder {
+    pub fn decode(&mut self, chunk: &[u8]) -> String {
+        let mut input = std::mem::take(&mut self.pending);
+        input.extend_from_slice(chunk);
+
+        let mut output = String::with_capacity(input.len());
+        let mut rest = input.as_slice();
+        loop {
+            match std::str::from_utf8(rest) {
+                Ok(valid) => {
+                    output.push_str(valid);
+                    break;
+                }
+                Err(e) => {
+                    let (valid, after) = rest.split_at(e.valid_up_to());
+                    // Valid prefix, so this borrows without replacing anything
+                    output.push_str(&String::from_utf8_lossy(valid));
+                    match e.error_len() {
+                        Some(len) => {
+                            output.push(char::REPLACEMENT_CHARACTER);
+                            rest = &after[len..];
+                        }
+                        None => {
+                            // Sequence is cut off at the end of the chunk
+                            self.pending.extend_from_slice(after);
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+        output
+    }
+
+    /// Bytes held back waiting for the rest of a multibyte sequence
+    pub fn take_pending(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.pending)
+    }
+}
+
+/// Turns raw channel data into the `data` field of a `terminal-data` event
+#[derive(Debug, Default)]
+pub struct OutputEncoder {
+    mode: OutputMode,
+    decoder: Utf8Decoder,
+}
+
+impl OutputEncoder {
+    pub fn new(mode: OutputMode) -> Self {
+        Self { mode, decoder: Utf8Decoder::default() }
+    }
+
+    pub fn mode(&self) -> OutputMode {
+        self.mode
+    }
+
+    /// Switch modes mid-stream (e.g. when a ZMODEM transfer starts).
+    /// Returns bytes that were buffered by the text decoder, which belong
+    /// in front of the next raw chunk.
+    pub fn set_mode(&mut self, mode: OutputMode) -> Vec<u8> {
+        self.mode = mode;
+        if mode == OutputMode::Text {
+            Vec::new()
+        } else {
+            self.decoder.take_pending()
+        }
+    }
+
+    pub fn encode(&mut self, data: &[u8]) -> serde_json::Value {
+        match self.mode {
+            OutputMode::Text => serde_json::Value::String(self.decoder.decode(data)),
+            OutputMode::Base64 => serde_json::Value::String(general_purpose::STANDARD.encode(data)),
+            OutputMode::Bytes => serde_json::json!(data),
+        }
+    }
+}
+
+/// Collects channel data into larger batches by time window and size
+#[derive(Debug, Default)]
+pub struct OutputBatcher {
+    buffer: Vec<u8>,
+    deadline: Option<Instant>,
+}
+
+impl OutputBatcher {
+    /// Buffer a chunk. Returns true when the batch is full and should be sent now.
+    pub fn push(&mut self, data: &[u8]) -> bool {
+        if data.is_empty() {
+            return false;
+        }
+        if self.buffer.is_empty() {
+            self.deadline = Some(Instant::now() + BATCH_WINDOW);
+        }
+        self.buffer.extend_from_slice(data);
+        self.buffer.len() >= MAX_BATCH_BYTES
+    }
+
+    /// When the current batch must be sent, if anything is buffered
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    pub fn take(&mut self) -> Vec<u8> {
+        self.deadline = None;
+        std::mem::take(&mut self.buffer)
+    }
+}
+
+/// Tracks output the renderer hasn't acknowledged yet, so a slow renderer
+/// pauses reading from the SSH channel instead of growing memory
+#[derive(Debug, Default)]
+pub struct FlowControl {
+    unacked: usize,
+    paused: bool,
+}
+
+impl FlowControl {
+    pub fn sent(&mut self, bytes: usize) {
+        self.unacked += bytes;
+        if self.unacked >= HIGH_WATERMARK {
+            self.paused = true;
+        }
+    }
+
+    pub fn ack(&mut self, bytes: usize) {
+        self.unacked = self.unacked.saturating_sub(bytes);
+        if self.unacked <= LOW_WATERMARK {
+            self.paused = false;
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+}
+
+/// Per-tab output path: encoding, batching and flow control in front of the
+/// ordered IPC channel passed to `connect_ssh`
+pub struct TerminalOutput {
+    channel: Channel<serde_json::Value>,
+    encoder: OutputEncoder,
+    batcher: OutputBatcher,
+    flow: FlowControl,
+}
+
+impl TerminalOutput {
+    pub fn new(channel: Channel<serde_json::Value>, mode: OutputMode) -> Self {
+        Self {
+            channel,
+            encoder: OutputEncoder::new(mode),
+            batcher: OutputBatcher::default(),
+            flow: FlowControl::default(),
+        }
+    }
+
+    pub fn push(&mut self, data: &[u8]) {
+        if self.batcher.push(data) {
+            self.flush();
+        }
+    }
+
+    pub fn flush(&mut self) {
+        let batch = self.batcher.take();
+        if batch.is_empty() {
+            return;
+        }
+
+        // `bytes` is what the renderer acknowledges once it has drawn the batch
+        let _ = self.channel.send(serde_json::json!({
+            "data": self.encoder.encode(&batch),
+            "encoding": self.encoder.mode(),
+            "bytes": batch.len(),
+        }));
+        self.flow.sent(batch.len());
+    }
+
+    pub fn set_mode(&mut self, mode: OutputMode) {
+        // Everything buffered so far still goes out in the old mode
+        self.flush();
+        let pending = self.encoder.set_mode(mode);
+        self.batcher.push(&pending);
+    }
+
+    pub fn ack(&mut self, bytes: usize) {
+        self.flow.ack(bytes);
+    }
+
+    pub fn deadline(&self) -> Option<Instant> {
+        self.batcher.deadline()
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.flow.is_paused()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_multibyte_split_across_chunks() {
+        let text = "Здравей 👋 ─┼─";
+        let bytes = text.as_bytes();
+        let mut decoder = Utf8Decoder::default();
+
+        // Feed one byte at a time, which splits every multibyte character
+        let decoded: String = bytes.iter().map(|b| decoder.decode(&[*b])).collect();
+        assert_eq!(decoded, text);
+        assert!(decoder.take_pending().is_empty());
+    }
+
+    #[test]
+    fn test_invalid_bytes_are_replaced() {
+        let mut decoder = Utf8Decoder::default();
+        assert_eq!(decoder.decode(b"a\xffb"), "a\u{FFFD}b");
+        assert_eq!(decoder.decode(b"\xd0"), "");
+        assert_eq!(decoder.decode(b"\xa7"), "Ч");
+    }
+
+    #[test]
+    fn test_switching_to_raw_keeps_pending_bytes() {
+        let mut encoder = OutputEncoder::new(OutputMode::Text);
+        assert_eq!(encoder.encode(b"ok\xe2\x94"), serde_json::json!("ok"));
+        assert_eq!(encoder.set_mode(OutputMode::Bytes), vec![0xe2, 0x94]);
+        assert_eq!(encoder.encode(&[0, 1]), serde_json::json!([0, 1]));
+
+        encoder.set_mode(OutputMode::Base64);
+        assert_eq!(encoder.encode(b"hi"), serde_json::json!("aGk="));
+    }
+
+    #[test]
+    fn test_batcher_flushes_when_full() {
+        let mut batcher = OutputBatcher::default();
+        assert!(batcher.deadline().is_none());
+        assert!(!batcher.push(b"hello"));
+        assert!(batcher.deadline().is_some());
+        assert!(batcher.push(&vec![0; MAX_BATCH_BYTES]));
+        assert_eq!(batcher.take().len(), MAX_BATCH_BYTES + 5);
+        assert!(batcher.deadline().is_none());
+    }
+
+    #[test]
+    fn test_flow_control_watermarks() {
+        let mut flow = FlowControl::default();
+        flow.sent(HIGH_WATERMARK - 1);
+        assert!(!flow.is_paused());
+        flow.sent(1);
+        assert!(flow.is_paused());
+        flow.ack(HIGH_WATERMARK - LOW_WATERMARK - 1);
+        assert!(flow.is_paused());
+        flow.ack(1);
+        assert!(!flow.is_paused());
+    }
+}
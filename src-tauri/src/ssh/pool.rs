@@ -0,0 +1,131 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use russh::Disconnect;
+use crate::db::{PortForward, SSHSession};
+use super::auth::AuthContext;
+use super::connection::{self, Connection};
+use super::SSHError;
+
+/// How long a connection stays open after its last channel closes, so that
+/// reopening a tab (or the next exec) doesn't have to log in again
+pub const GRACE_PERIOD: Duration = Duration::from_secs(30);
+
+/// Connections shared by every tab and exec opened from the same saved session
+#[derive(Default)]
+struct PoolEntry {
+    /// Held while dialing, so tabs opened together share one login
+    dial: tokio::sync::Mutex<()>,
+    state: Mutex<PoolState>,
+}
+
+#[derive(Default)]
+struct PoolState {
+    connection: Option<Arc<Connection>>,
+    /// Remote forwards requested at connect time, until a tab takes them
+    unclaimed_forwards: Option<Vec<(PortForward, Result<(), SSHError>)>>,
+    /// Open leases, i.e. channels using the connection
+    channels: usize,
+    /// Bumped on every acquire and release, so a pending teardown can tell
+    /// whether the connection was picked up again during its grace period
+    generation: u64,
+}
+
+lazy_static::lazy_static! {
+    static ref POOL: Mutex<HashMap<String, Arc<PoolEntry>>> = Mutex::new(HashMap::new());
+}
+
+/// A reference to a pooled connection. Dropping it releases the reference;
+/// the last one to go starts the grace period.
+pub struct Lease {
+    session_id: String,
+    entry: Arc<PoolEntry>,
+    pub connection: Arc<Connection>,
+}
+
+impl Lease {
+    /// Take the session's port forwards, if no other tab has yet. The tab
+    /// that gets them starts them on the connection's `forwards`, where they
+    /// keep running after that tab closes.
+    pub fn claim_forwards(&self) -> Option<Vec<(PortForward, Result<(), SSHError>)>> {
+        self.entry.state.lock().unwrap().unclaimed_forwards.take()
+    }
+}
+
+impl Drop for Lease {
+    fn drop(&mut self) {
+        let mut state = self.entry.state.lock().unwrap();
+        state.channels = state.channels.saturating_sub(1);
+        state.generation += 1;
+        if state.channels > 0 {
+            return;
+        }
+
+        let generation = state.generation;
+        let session_id = self.session_id.clone();
+        let entry = self.entry.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(GRACE_PERIOD).await;
+            teardown(&session_id, &entry, generation).await;
+        });
+    }
+}
+
+/// Get the live connection for `config`, dialing and authenticating only
+/// if there isn't one yet (or the old one has dropped)
+pub async fn acquire(ctx: &AuthContext<'_>, config: &SSHSession) -> Result<Lease, SSHError> {
+    let entry = POOL.lock().unwrap().entry(config.id.clone()).or_default().clone();
+    let _dialing = entry.dial.lock().await;
+
+    if let Some(connection) = lease_existing(&entry) {
+        return Ok(Lease { session_id: config.id.clone(), entry, connection });
+    }
+
+    let mut connection = connection::open(ctx, config).await?;
+    let remote_forwards = std::mem::take(&mut connection.remote_forwards);
+    let connection = Arc::new(connection);
+
+    let stale = {
+        let mut state = entry.state.lock().unwrap();
+        state.unclaimed_forwards = Some(remote_forwards);
+        state.channels += 1;
+        state.generation += 1;
+        state.connection.replace(connection.clone())
+    };
+    if let Some(stale) = stale {
+        let _ = stale.handle.disconnect(Disconnect::ByApplication, "", "English").await;
+    }
+
+    Ok(Lease { session_id: config.id.clone(), entry, connection })
+}
+
+fn lease_existing(entry: &PoolEntry) -> Option<Arc<Connection>> {
+    let mut state = entry.state.lock().unwrap();
+    let connection = state.connection.clone()?;
+    if connection.handle.is_closed() {
+        return None;
+    }
+    state.channels += 1;
+    state.generation += 1;
+    Some(connection)
+}
+
+/// Close the connection if nothing used it since `generation`
+async fn teardown(session_id: &str, entry: &Arc<PoolEntry>, generation: u64) {
+    let connection = {
+        let mut pool = POOL.lock().unwrap();
+        let mut state = entry.state.lock().unwrap();
+        if state.generation != generation || state.channels > 0 {
+            return;
+        }
+        if matches!(pool.get(session_id), Some(current) if Arc::ptr_eq(current, entry)) {
+            pool.remove(session_id);
+        }
+        state.unclaimed_forwards = None;
+        state.connection.take()
+    };
+
+    if let Some(connection) = connection {
+        let _ = connection.handle.disconnect(Disconnect::ByApplication, "", "English").await;
+    }
+}
@@ -0,0 +1,51 @@
+use std::collections::HashMap;
+use std::time::Duration;
+use serde::de::DeserializeOwned;
+use tauri::{AppHandle, Emitter};
+use tokio::sync::{oneshot, Mutex};
+use uuid::Uuid;
+use super::SSHError;
+
+/// How long a connection waits for the user to answer a prompt
+const PROMPT_TIMEOUT: Duration = Duration::from_secs(300);
+
+// Prompts waiting for an answer from the frontend, keyed by prompt id
+lazy_static::lazy_static! {
+    static ref PENDING_PROMPTS: Mutex<HashMap<String, oneshot::Sender<serde_json::Value>>> = Mutex::new(HashMap::new());
+}
+
+/// Emit `event` with `payload` (plus a generated `prompt_id`) and wait for the
+/// frontend to answer it through `answer`.
+pub async fn ask<T: DeserializeOwned>(
+    app_handle: &AppHandle,
+    event: &str,
+    mut payload: serde_json::Value,
+) -> Result<T, SSHError> {
+    let prompt_id = Uuid::new_v4().to_string();
+    let (tx, rx) = oneshot::channel();
+    PENDING_PROMPTS.lock().await.insert(prompt_id.clone(), tx);
+
+    payload["prompt_id"] = serde_json::Value::String(prompt_id.clone());
+    let _ = app_handle.emit(event, payload);
+
+    let reply = tokio::time::timeout(PROMPT_TIMEOUT, rx).await;
+    PENDING_PROMPTS.lock().await.remove(&prompt_id);
+
+    match reply {
+        Ok(Ok(value)) => serde_json::from_value(value)
+            .map_err(|e| SSHError::PromptCancelled(format!("Invalid answer: {}", e))),
+        Ok(Err(_)) => Err(SSHError::PromptCancelled("Prompt was dropped".to_string())),
+        Err(_) => Err(SSHError::PromptCancelled("Timed out waiting for an answer".to_string())),
+    }
+}
+
+/// Deliver the frontend's answer to a pending prompt
+pub async fn answer(prompt_id: &str, value: serde_json::Value) -> Result<(), SSHError> {
+    let tx = PENDING_PROMPTS
+        .lock()
+        .await
+        .remove(prompt_id)
+        .ok_or_else(|| SSHError::PromptCancelled(format!("No pending prompt {}", prompt_id)))?;
+    tx.send(value)
+        .map_err(|_| SSHError::PromptCancelled("Connection is no longer waiting".to_string()))
+}
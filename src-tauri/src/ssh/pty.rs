@@ -0,0 +1,160 @@
+use russh::Pty;
+use crate::db::SSHSession;
+
+/// TERM sent when the session doesn't set one; what xterm.js emulates
+pub const DEFAULT_TERM: &str = "xterm-256color";
+
+/// TERM for the session's pty request
+pub fn term(config: &SSHSession) -> &str {
+    match config.term.as_deref().map(str::trim) {
+        Some(term) if !term.is_empty() => term,
+        _ => DEFAULT_TERM,
+    }
+}
+
+/// The session's terminal modes as pty request opcodes. A name we don't
+/// know is skipped, since a mistyped mode isn't worth a terminal that
+/// won't open.
+pub fn modes(config: &SSHSession) -> Vec<(Pty, u32)> {
+    config
+        .terminal_modes
+        .iter()
+        .filter_map(|mode| match opcode(&mode.name) {
+            Some(pty) => Some((pty, mode.value)),
+            None => {
+                eprintln!("Skipping unknown terminal mode {:?} for {}", mode.name, config.host);
+                None
+            }
+        })
+        .collect()
+}
+
+/// Environment variables to send, skipping names the protocol can't carry
+pub fn env(config: &SSHSession) -> impl Iterator<Item = (&str, &str)> {
+    config
+        .env
+        .iter()
+        .map(|(name, value)| (name.trim(), value.as_str()))
+        .filter(|(name, _)| !name.is_empty() && !name.contains('='))
+}
+
+/// Look up a terminal mode by its RFC 4254 name, case-insensitively
+fn opcode(name: &str) -> Option<Pty> {
+    let pty = match name.trim().to_ascii_uppercase().as_str() {
+        "VINTR" => Pty::VINTR,
+        "VQUIT" => Pty::VQUIT,
+        "VERASE" => Pty::VERASE,
+        "VKILL" => Pty::VKILL,
+        "VEOF" => Pty::VEOF,
+        "VEOL" => Pty::VEOL,
+        "VEOL2" => Pty::VEOL2,
+        "VSTART" => Pty::VSTART,
+        "VSTOP" => Pty::VSTOP,
+        "VSUSP" => Pty::VSUSP,
+        "VDSUSP" => Pty::VDSUSP,
+        "VREPRINT" => Pty::VREPRINT,
+        "VWERASE" => Pty::VWERASE,
+        "VLNEXT" => Pty::VLNEXT,
+        "VFLUSH" => Pty::VFLUSH,
+        "VSWTCH" => Pty::VSWTCH,
+        "VSTATUS" => Pty::VSTATUS,
+        "VDISCARD" => Pty::VDISCARD,
+        "IGNPAR" => Pty::IGNPAR,
+        "PARMRK" => Pty::PARMRK,
+        "INPCK" => Pty::INPCK,
+        "ISTRIP" => Pty::ISTRIP,
+        "INLCR" => Pty::INLCR,
+        "IGNCR" => Pty::IGNCR,
+        "ICRNL" => Pty::ICRNL,
+        "IUCLC" => Pty::IUCLC,
+        "IXON" => Pty::IXON,
+        "IXANY" => Pty::IXANY,
+        "IXOFF" => Pty::IXOFF,
+        "IMAXBEL" => Pty::IMAXBEL,
+        "ISIG" => Pty::ISIG,
+        "ICANON" => Pty::ICANON,
+        "XCASE" => Pty::XCASE,
+        "ECHO" => Pty::ECHO,
+        "ECHOE" => Pty::ECHOE,
+        "ECHOK" => Pty::ECHOK,
+        "ECHONL" => Pty::ECHONL,
+        "NOFLSH" => Pty::NOFLSH,
+        "TOSTOP" => Pty::TOSTOP,
+        "IEXTEN" => Pty::IEXTEN,
+        "ECHOCTL" => Pty::ECHOCTL,
+        "ECHOKE" => Pty::ECHOKE,
+        "PENDIN" => Pty::PENDIN,
+        "OPOST" => Pty::OPOST,
+        "OLCUC" => Pty::OLCUC,
+        "ONLCR" => Pty::ONLCR,
+        "OCRNL" => Pty::OCRNL,
+        "ONOCR" => Pty::ONOCR,
+        "ONLRET" => Pty::ONLRET,
+        "CS7" => Pty::CS7,
+        "CS8" => Pty::CS8,
+        "PARENB" => Pty::PARENB,
+        "PARODD" => Pty::PARODD,
+        "TTY_OP_ISPEED" => Pty::TTY_OP_ISPEED,
+        "TTY_OP_OSPEED" => Pty::TTY_OP_OSPEED,
+        _ => return None,
+    };
+    Some(pty)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::TerminalMode;
+
+    fn session() -> SSHSession {
+        serde_json::from_value(serde_json::json!({
+            "id": "s1",
+            "name": "web",
+            "host": "web.example.com",
+            "port": 22,
+            "username": "deploy",
+            "authType": "agent",
+            "password": null,
+            "privateKey": null,
+            "createdAt": "",
+            "updatedAt": "",
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_term_defaults() {
+        let mut config = session();
+        assert_eq!(term(&config), DEFAULT_TERM);
+        config.term = Some("  ".to_string());
+        assert_eq!(term(&config), DEFAULT_TERM);
+        config.term = Some("screen-256color".to_string());
+        assert_eq!(term(&config), "screen-256color");
+    }
+
+    #[test]
+    fn test_modes_by_name() {
+        let mut config = session();
+        config.terminal_modes = vec![
+            TerminalMode { name: "verase".to_string(), value: 127 },
+            TerminalMode { name: "ECHO".to_string(), value: 1 },
+        ];
+        assert_eq!(modes(&config).len(), 2);
+
+        config.terminal_modes.insert(1, TerminalMode { name: "VBOGUS".to_string(), value: 0 });
+        let opcodes: Vec<u32> = modes(&config).into_iter().map(|(_, value)| value).collect();
+        assert_eq!(opcodes, vec![127, 1]);
+    }
+
+    #[test]
+    fn test_env_skips_invalid_names() {
+        let mut config = session();
+        config.env.insert("LANG".to_string(), "bg_BG.UTF-8".to_string());
+        config.env.insert("LC_TIME".to_string(), "bg_BG.UTF-8".to_string());
+        config.env.insert("A=B".to_string(), "x".to_string());
+        config.env.insert(" ".to_string(), "x".to_string());
+
+        let names: Vec<&str> = env(&config).map(|(name, _)| name).collect();
+        assert_eq!(names, vec!["LANG", "LC_TIME"]);
+    }
+}
@@ -0,0 +1,70 @@
+use std::time::Duration;
+
+/// Wait before the first reconnect attempt
+const INITIAL_DELAY: Duration = Duration::from_secs(1);
+/// The delay doubles after each failed attempt up to this
+const MAX_DELAY: Duration = Duration::from_secs(60);
+
+/// Exponential backoff between reconnect attempts
+pub struct Backoff {
+    attempt: u32,
+    max_attempts: u32,
+}
+
+impl Backoff {
+    pub fn new(max_attempts: u32) -> Self {
+        Self { attempt: 0, max_attempts }
+    }
+
+    /// Delay before the next attempt, or None once all attempts are used
+    pub fn next_delay(&mut self) -> Option<Duration> {
+        if self.attempt >= self.max_attempts {
+            return None;
+        }
+        let delay = INITIAL_DELAY
+            .checked_mul(1 << self.attempt.min(16))
+            .map_or(MAX_DELAY, |delay| delay.min(MAX_DELAY));
+        self.attempt += 1;
+        Some(delay)
+    }
+
+    /// Number of the attempt the last delay was for, starting at 1
+    pub fn attempt(&self) -> u32 {
+        self.attempt
+    }
+
+    /// Start over after a successful reconnect
+    pub fn reset(&mut self) {
+        self.attempt = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_doubles_up_to_max() {
+        let mut backoff = Backoff::new(10);
+        let delays: Vec<u64> = std::iter::from_fn(|| backoff.next_delay()).map(|d| d.as_secs()).collect();
+        assert_eq!(delays, vec![1, 2, 4, 8, 16, 32, 60, 60, 60, 60]);
+        assert_eq!(backoff.attempt(), 10);
+    }
+
+    #[test]
+    fn test_backoff_reset() {
+        let mut backoff = Backoff::new(2);
+        backoff.next_delay();
+        backoff.next_delay();
+        assert_eq!(backoff.next_delay(), None);
+
+        backoff.reset();
+        assert_eq!(backoff.next_delay(), Some(Duration::from_secs(1)));
+        assert_eq!(backoff.attempt(), 1);
+    }
+
+    #[test]
+    fn test_backoff_disabled() {
+        assert_eq!(Backoff::new(0).next_delay(), None);
+    }
+}
@@ -0,0 +1,232 @@
+use std::io::{Error, ErrorKind};
+use std::net::{Ipv4Addr, Ipv6Addr};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+const SOCKS4: u8 = 4;
+const SOCKS5: u8 = 5;
+const CMD_CONNECT: u8 = 1;
+
+const SOCKS4_GRANTED: u8 = 0x5a;
+const SOCKS4_REJECTED: u8 = 0x5b;
+
+const SOCKS5_NO_AUTH: u8 = 0x00;
+const SOCKS5_NO_ACCEPTABLE_METHODS: u8 = 0xff;
+const SOCKS5_SUCCEEDED: u8 = 0x00;
+const SOCKS5_HOST_UNREACHABLE: u8 = 0x04;
+const SOCKS5_COMMAND_NOT_SUPPORTED: u8 = 0x07;
+const SOCKS5_ADDRESS_NOT_SUPPORTED: u8 = 0x08;
+
+const ATYP_IPV4: u8 = 1;
+const ATYP_DOMAIN: u8 = 3;
+const ATYP_IPV6: u8 = 4;
+
+/// Longest SOCKS4 user id or SOCKS4a host name we accept
+const MAX_NUL_STRING: usize = 255;
+
+/// A CONNECT request read from a SOCKS client
+#[derive(Debug, PartialEq, Eq)]
+pub struct ConnectRequest {
+    pub version: u8,
+    /// Host name or IP literal. Names are resolved by the SSH server.
+    pub host: String,
+    pub port: u16,
+}
+
+/// Read the greeting and CONNECT request of a SOCKS4, SOCKS4a or SOCKS5
+/// client. Requests we can't serve are answered with an error reply.
+pub async fn read_request<S>(stream: &mut S) -> std::io::Result<ConnectRequest>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    match stream.read_u8().await? {
+        SOCKS4 => read_socks4(stream).await,
+        SOCKS5 => read_socks5(stream).await,
+        version => Err(invalid(format!("unsupported SOCKS version {}", version))),
+    }
+}
+
+/// Tell the client whether the tunnel to its target was opened
+pub async fn reply<S>(stream: &mut S, version: u8, success: bool) -> std::io::Result<()>
+where
+    S: AsyncWrite + Unpin,
+{
+    if version == SOCKS4 {
+        let status = if success { SOCKS4_GRANTED } else { SOCKS4_REJECTED };
+        stream.write_all(&[0, status, 0, 0, 0, 0, 0, 0]).await
+    } else {
+        let status = if success { SOCKS5_SUCCEEDED } else { SOCKS5_HOST_UNREACHABLE };
+        socks5_reply(stream, status).await
+    }
+}
+
+async fn read_socks4<S>(stream: &mut S) -> std::io::Result<ConnectRequest>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let command = stream.read_u8().await?;
+    let port = stream.read_u16().await?;
+    let mut ip = [0; 4];
+    stream.read_exact(&mut ip).await?;
+    let _user_id = read_nul_string(stream).await?;
+
+    if command != CMD_CONNECT {
+        reply(stream, SOCKS4, false).await?;
+        return Err(invalid(format!("unsupported SOCKS4 command {}", command)));
+    }
+
+    // SOCKS4a: an address of 0.0.0.x (x != 0) means a host name follows
+    let host = if ip[..3] == [0, 0, 0] && ip[3] != 0 {
+        read_nul_string(stream).await?
+    } else {
+        Ipv4Addr::from(ip).to_string()
+    };
+
+    Ok(ConnectRequest { version: SOCKS4, host, port })
+}
+
+async fn read_socks5<S>(stream: &mut S) -> std::io::Result<ConnectRequest>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let method_count = stream.read_u8().await? as usize;
+    let mut methods = vec![0; method_count];
+    stream.read_exact(&mut methods).await?;
+
+    // The proxy only listens locally, so there is nothing to authenticate
+    if !methods.contains(&SOCKS5_NO_AUTH) {
+        stream.write_all(&[SOCKS5, SOCKS5_NO_ACCEPTABLE_METHODS]).await?;
+        return Err(invalid("SOCKS5 client requires authentication".to_string()));
+    }
+    stream.write_all(&[SOCKS5, SOCKS5_NO_AUTH]).await?;
+
+    let mut header = [0; 4];
+    stream.read_exact(&mut header).await?;
+    let [version, command, _reserved, address_type] = header;
+    if version != SOCKS5 {
+        return Err(invalid(format!("unexpected SOCKS version {} in request", version)));
+    }
+
+    let host = match address_type {
+        ATYP_IPV4 => {
+            let mut ip = [0; 4];
+            stream.read_exact(&mut ip).await?;
+            Ipv4Addr::from(ip).to_string()
+        }
+        ATYP_IPV6 => {
+            let mut ip = [0; 16];
+            stream.read_exact(&mut ip).await?;
+            Ipv6Addr::from(ip).to_string()
+        }
+        ATYP_DOMAIN => {
+            let len = stream.read_u8().await? as usize;
+            let mut name = vec![0; len];
+            stream.read_exact(&mut name).await?;
+            String::from_utf8(name).map_err(|_| invalid("host name is not UTF-8".to_string()))?
+        }
+        other => {
+            socks5_reply(stream, SOCKS5_ADDRESS_NOT_SUPPORTED).await?;
+            return Err(invalid(format!("unsupported SOCKS5 address type {}", other)));
+        }
+    };
+    let port = stream.read_u16().await?;
+
+    if command != CMD_CONNECT {
+        socks5_reply(stream, SOCKS5_COMMAND_NOT_SUPPORTED).await?;
+        return Err(invalid(format!("unsupported SOCKS5 command {}", command)));
+    }
+
+    Ok(ConnectRequest { version: SOCKS5, host, port })
+}
+
+async fn socks5_reply<S>(stream: &mut S, status: u8) -> std::io::Result<()>
+where
+    S: AsyncWrite + Unpin,
+{
+    // The bound address is meaningless for a tunnel, so report 0.0.0.0:0
+    stream.write_all(&[SOCKS5, status, 0, ATYP_IPV4, 0, 0, 0, 0, 0, 0]).await
+}
+
+async fn read_nul_string<S>(stream: &mut S) -> std::io::Result<String>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut bytes = Vec::new();
+    loop {
+        match stream.read_u8().await? {
+            0 => break,
+            byte if bytes.len() < MAX_NUL_STRING => bytes.push(byte),
+            _ => return Err(invalid("SOCKS4 string too long".to_string())),
+        }
+    }
+    String::from_utf8(bytes).map_err(|_| invalid("SOCKS4 string is not UTF-8".to_string()))
+}
+
+fn invalid(message: String) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn run(input: &[u8]) -> (std::io::Result<ConnectRequest>, Vec<u8>) {
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        client.write_all(input).await.unwrap();
+
+        let result = read_request(&mut server).await;
+        if let Ok(request) = &result {
+            reply(&mut server, request.version, true).await.unwrap();
+        }
+        drop(server);
+
+        let mut written = Vec::new();
+        client.read_to_end(&mut written).await.unwrap();
+        (result, written)
+    }
+
+    #[tokio::test]
+    async fn test_socks5_domain_connect() {
+        let mut input = vec![SOCKS5, 1, SOCKS5_NO_AUTH, SOCKS5, CMD_CONNECT, 0, ATYP_DOMAIN, 12];
+        input.extend_from_slice(b"intranet.lan");
+        input.extend_from_slice(&8080u16.to_be_bytes());
+
+        let (request, written) = run(&input).await;
+        assert_eq!(
+            request.unwrap(),
+            ConnectRequest { version: SOCKS5, host: "intranet.lan".to_string(), port: 8080 }
+        );
+        assert_eq!(written, [SOCKS5, SOCKS5_NO_AUTH, SOCKS5, SOCKS5_SUCCEEDED, 0, ATYP_IPV4, 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[tokio::test]
+    async fn test_socks5_rejects_bind_and_auth_only_clients() {
+        let input = [SOCKS5, 1, SOCKS5_NO_AUTH, SOCKS5, 2, 0, ATYP_IPV4, 10, 0, 0, 1, 0, 80];
+        let (request, written) = run(&input).await;
+        assert!(request.is_err());
+        assert_eq!(written[3], SOCKS5_COMMAND_NOT_SUPPORTED);
+
+        // Only username/password offered
+        let (request, written) = run(&[SOCKS5, 1, 2]).await;
+        assert!(request.is_err());
+        assert_eq!(written, [SOCKS5, SOCKS5_NO_ACCEPTABLE_METHODS]);
+    }
+
+    #[tokio::test]
+    async fn test_socks4_and_socks4a() {
+        let input = [SOCKS4, CMD_CONNECT, 0, 22, 192, 168, 1, 10, b'm', b'e', 0];
+        let (request, written) = run(&input).await;
+        assert_eq!(
+            request.unwrap(),
+            ConnectRequest { version: SOCKS4, host: "192.168.1.10".to_string(), port: 22 }
+        );
+        assert_eq!(written, [0, SOCKS4_GRANTED, 0, 0, 0, 0, 0, 0]);
+
+        let mut input = vec![SOCKS4, CMD_CONNECT, 1, 187, 0, 0, 0, 1, 0];
+        input.extend_from_slice(b"grafana.internal\0");
+        let (request, _) = run(&input).await;
+        assert_eq!(
+            request.unwrap(),
+            ConnectRequest { version: SOCKS4, host: "grafana.internal".to_string(), port: 443 }
+        );
+    }
+}
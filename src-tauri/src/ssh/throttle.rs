@@ -0,0 +1,131 @@
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// Stops reading a connection's transport while one of its tabs has more
+/// output than its renderer has acknowledged.
+///
+/// russh 0.42 moves channel data into an unbounded per-channel queue and
+/// sends `WINDOW_ADJUST` as soon as it reads the data off the transport,
+/// whether or not anyone reads the channel. Leaving a channel unread
+/// therefore only moves the backlog into russh; the server is throttled
+/// only once the transport isn't read, which lets the channel window run
+/// out. That holds up every channel on the connection, so other tabs on
+/// it wait for the slow renderer as well. A pause longer than the
+/// keepalive timeout drops the connection, as the replies aren't read.
+#[derive(Debug, Clone, Default)]
+pub struct ReadGate {
+    inner: Arc<GateState>,
+}
+
+#[derive(Debug, Default)]
+struct GateState {
+    holds: AtomicUsize,
+    /// The transport read that found the gate closed
+    waker: Mutex<Option<Waker>>,
+}
+
+/// Keeps the gate closed until dropped
+#[derive(Debug)]
+pub struct ReadHold {
+    inner: Arc<GateState>,
+}
+
+impl ReadGate {
+    pub fn hold(&self) -> ReadHold {
+        self.inner.holds.fetch_add(1, Ordering::SeqCst);
+        ReadHold { inner: self.inner.clone() }
+    }
+
+    pub fn is_held(&self) -> bool {
+        self.inner.holds.load(Ordering::SeqCst) > 0
+    }
+}
+
+impl Drop for ReadHold {
+    fn drop(&mut self) {
+        if self.inner.holds.fetch_sub(1, Ordering::SeqCst) == 1 {
+            if let Some(waker) = self.inner.waker.lock().unwrap().take() {
+                waker.wake();
+            }
+        }
+    }
+}
+
+/// A transport that doesn't read while its gate is held. Writes go through,
+/// so input typed meanwhile still reaches the server.
+pub struct GatedStream<S> {
+    inner: Pin<Box<S>>,
+    gate: ReadGate,
+}
+
+impl<S> GatedStream<S> {
+    pub fn new(inner: S, gate: ReadGate) -> Self {
+        Self { inner: Box::pin(inner), gate }
+    }
+}
+
+impl<S: AsyncRead> AsyncRead for GatedStream<S> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        if self.gate.is_held() {
+            *self.gate.inner.waker.lock().unwrap() = Some(cx.waker().clone());
+            // Released between the check and storing the waker
+            if self.gate.is_held() {
+                return Poll::Pending;
+            }
+        }
+        self.inner.as_mut().poll_read(cx, buf)
+    }
+}
+
+impl<S: AsyncWrite> AsyncWrite for GatedStream<S> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        self.inner.as_mut().poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.inner.as_mut().poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.inner.as_mut().poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn test_held_gate_stops_reads() {
+        let (mut server, transport) = tokio::io::duplex(64);
+        let gate = ReadGate::default();
+        let mut stream = GatedStream::new(transport, gate.clone());
+        server.write_all(b"data").await.unwrap();
+
+        let first = gate.hold();
+        let second = gate.hold();
+        let mut buf = [0u8; 4];
+        let read = tokio::time::timeout(Duration::from_millis(50), stream.read_exact(&mut buf)).await;
+        assert!(read.is_err());
+
+        // Writing still works while reads are held
+        stream.write_all(b"input").await.unwrap();
+        let mut input = [0u8; 5];
+        server.read_exact(&mut input).await.unwrap();
+        assert_eq!(&input, b"input");
+
+        drop(first);
+        let read = tokio::time::timeout(Duration::from_millis(50), stream.read_exact(&mut buf)).await;
+        assert!(read.is_err());
+
+        drop(second);
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"data");
+    }
+}
@@ -0,0 +1,273 @@
+use std::path::PathBuf;
+use std::sync::atomic::AtomicU64;
+use std::sync::Arc;
+use ring::rand::{SecureRandom, SystemRandom};
+use russh::{client, Channel};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use super::{forward, SSHError};
+
+/// The only X authorization protocol we forward
+pub const MIT_MAGIC_COOKIE: &str = "MIT-MAGIC-COOKIE-1";
+const COOKIE_LEN: usize = 16;
+/// X servers listen on TCP port 6000 + display number
+const X_TCP_PORT_BASE: u32 = 6000;
+
+/// Where the local X server listens
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DisplayAddress {
+    Unix(PathBuf),
+    Tcp(String, u16),
+}
+
+/// A parsed `$DISPLAY`, e.g. `:0`, `localhost:10.0` or XQuartz's socket path
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Display {
+    pub address: DisplayAddress,
+    pub number: u32,
+    pub screen: u32,
+}
+
+pub fn parse_display(display: &str) -> Option<Display> {
+    let (host, rest) = display.rsplit_once(':')?;
+    let (number, screen) = match rest.split_once('.') {
+        Some((number, screen)) => (number.parse().ok()?, screen.parse().ok()?),
+        None => (rest.parse().ok()?, 0),
+    };
+
+    let address = if host.starts_with('/') {
+        // XQuartz: the whole value, display number included, is the socket path
+        DisplayAddress::Unix(PathBuf::from(display))
+    } else if host.is_empty() || host == "unix" {
+        DisplayAddress::Unix(PathBuf::from(format!("/tmp/.X11-unix/X{}", number)))
+    } else {
+        let port = u16::try_from(X_TCP_PORT_BASE + number).ok()?;
+        DisplayAddress::Tcp(host.to_string(), port)
+    };
+
+    Some(Display { address, number, screen })
+}
+
+/// X11 forwarding state for one connection. The server only ever sees a
+/// random fake cookie; X clients present it when they connect back, and we
+/// swap in the real one (if the display has one) before reaching the X server.
+pub struct X11Forwarding {
+    display: Display,
+    fake_cookie: [u8; COOKIE_LEN],
+    real_cookie: Option<Vec<u8>>,
+}
+
+impl X11Forwarding {
+    /// Forward to the display in `$DISPLAY`, using its cookie from `xauth`
+    pub async fn from_env() -> Result<Self, SSHError> {
+        let name = std::env::var("DISPLAY")
+            .map_err(|_| SSHError::Unsupported("X11 forwarding without $DISPLAY".to_string()))?;
+        let display = parse_display(&name)
+            .ok_or_else(|| SSHError::Unsupported(format!("X11 display {:?}", name)))?;
+
+        let mut fake_cookie = [0; COOKIE_LEN];
+        SystemRandom::new()
+            .fill(&mut fake_cookie)
+            .map_err(|_| SSHError::ConnectionFailed("Could not generate an X11 cookie".to_string()))?;
+
+        Ok(Self { display, fake_cookie, real_cookie: real_cookie(&name).await })
+    }
+
+    pub fn fake_cookie_hex(&self) -> String {
+        self.fake_cookie.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    pub fn screen(&self) -> u32 {
+        self.display.screen
+    }
+}
+
+/// The display's cookie, as `xauth list` reports it (like OpenSSH does).
+/// None if xauth isn't installed or the display doesn't use one.
+async fn real_cookie(display: &str) -> Option<Vec<u8>> {
+    let output = tokio::process::Command::new("xauth")
+        .args(["list", display])
+        .output()
+        .await
+        .ok()?;
+    parse_xauth_list(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// Pick the MIT cookie out of `xauth list` lines: `host/unix:0  MIT-MAGIC-COOKIE-1  <hex>`
+fn parse_xauth_list(output: &str) -> Option<Vec<u8>> {
+    output.lines().find_map(|line| {
+        let mut fields = line.split_whitespace().skip(1);
+        if fields.next()? != MIT_MAGIC_COOKIE {
+            return None;
+        }
+        decode_hex(fields.next()?)
+    })
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Serve an `x11` channel the server opened for a remote X client
+pub fn serve(forwarding: Arc<X11Forwarding>, channel: Channel<client::Msg>, originator: String) {
+    tokio::spawn(async move {
+        if let Err(e) = relay_to_display(&forwarding, channel).await {
+            eprintln!("X11 connection from {} failed: {}", originator, e);
+        }
+    });
+}
+
+async fn relay_to_display(forwarding: &X11Forwarding, channel: Channel<client::Msg>) -> Result<(), SSHError> {
+    let mut remote = channel.into_stream();
+    let setup = read_setup(&mut remote).await?;
+
+    if setup.auth_name != MIT_MAGIC_COOKIE.as_bytes() || setup.auth_data != forwarding.fake_cookie {
+        return Err(SSHError::Forward("X11 client presented the wrong cookie".to_string()));
+    }
+    let setup = match &forwarding.real_cookie {
+        Some(cookie) => setup.with_auth(MIT_MAGIC_COOKIE.as_bytes(), cookie),
+        // The display doesn't use a cookie, so don't send one either
+        None => setup.with_auth(b"", b""),
+    };
+
+    let (sent, received) = (AtomicU64::new(0), AtomicU64::new(0));
+    match &forwarding.display.address {
+        #[cfg(unix)]
+        DisplayAddress::Unix(path) => {
+            let mut local = tokio::net::UnixStream::connect(path).await?;
+            local.write_all(&setup.encode()).await?;
+            forward::relay_stream(local, remote, &received, &sent).await;
+        }
+        #[cfg(not(unix))]
+        DisplayAddress::Unix(path) => {
+            return Err(SSHError::Unsupported(format!("X11 socket {}", path.display())));
+        }
+        DisplayAddress::Tcp(host, port) => {
+            let mut local = tokio::net::TcpStream::connect((host.as_str(), *port)).await?;
+            local.write_all(&setup.encode()).await?;
+            forward::relay_stream(local, remote, &received, &sent).await;
+        }
+    }
+    Ok(())
+}
+
+/// The connection setup an X client sends first: a 12-byte header, then
+/// the authorization protocol name and data, each padded to 4 bytes
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Setup {
+    header: [u8; 12],
+    auth_name: Vec<u8>,
+    auth_data: Vec<u8>,
+}
+
+impl Setup {
+    /// Whether the client's byte order is big-endian ('B') rather than 'l'
+    fn big_endian(&self) -> bool {
+        self.header[0] == b'B'
+    }
+
+    fn read_u16(&self, offset: usize) -> u16 {
+        let bytes = [self.header[offset], self.header[offset + 1]];
+        if self.big_endian() {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        }
+    }
+
+    fn write_u16(&mut self, offset: usize, value: u16) {
+        let bytes = if self.big_endian() { value.to_be_bytes() } else { value.to_le_bytes() };
+        self.header[offset..offset + 2].copy_from_slice(&bytes);
+    }
+
+    fn with_auth(mut self, name: &[u8], data: &[u8]) -> Self {
+        self.write_u16(6, name.len() as u16);
+        self.write_u16(8, data.len() as u16);
+        self.auth_name = name.to_vec();
+        self.auth_data = data.to_vec();
+        self
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut out = self.header.to_vec();
+        for field in [&self.auth_name, &self.auth_data] {
+            out.extend_from_slice(field);
+            out.resize(out.len() + padding(field.len()), 0);
+        }
+        out
+    }
+}
+
+async fn read_setup<R: AsyncRead + Unpin>(reader: &mut R) -> Result<Setup, SSHError> {
+    let mut setup = Setup { header: [0; 12], auth_name: Vec::new(), auth_data: Vec::new() };
+    reader.read_exact(&mut setup.header).await?;
+    if !matches!(setup.header[0], b'B' | b'l') {
+        return Err(SSHError::Forward("Not an X11 connection setup".to_string()));
+    }
+
+    let (name_len, data_len) = (setup.read_u16(6) as usize, setup.read_u16(8) as usize);
+    setup.auth_name = read_padded(reader, name_len).await?;
+    setup.auth_data = read_padded(reader, data_len).await?;
+    Ok(setup)
+}
+
+async fn read_padded<R: AsyncRead + Unpin>(reader: &mut R, len: usize) -> Result<Vec<u8>, SSHError> {
+    let mut buf = vec![0; len + padding(len)];
+    reader.read_exact(&mut buf).await?;
+    buf.truncate(len);
+    Ok(buf)
+}
+
+fn padding(len: usize) -> usize {
+    (4 - len % 4) % 4
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_display() {
+        assert_eq!(
+            parse_display(":0"),
+            Some(Display { address: DisplayAddress::Unix("/tmp/.X11-unix/X0".into()), number: 0, screen: 0 })
+        );
+        assert_eq!(
+            parse_display("localhost:10.1"),
+            Some(Display { address: DisplayAddress::Tcp("localhost".to_string(), 6010), number: 10, screen: 1 })
+        );
+
+        let xquartz = "/private/tmp/com.apple.launchd.abc/org.xquartz:0";
+        assert_eq!(parse_display(xquartz).unwrap().address, DisplayAddress::Unix(xquartz.into()));
+        assert_eq!(parse_display("nodisplay"), None);
+    }
+
+    #[test]
+    fn test_parse_xauth_list() {
+        let output = "laptop/unix:0  XDM-AUTHORIZATION-1  00ff\nlaptop/unix:0  MIT-MAGIC-COOKIE-1  0a0b0c0d0e0f10111213141516171819\n";
+        assert_eq!(parse_xauth_list(output), Some((10..26).collect()));
+        assert_eq!(parse_xauth_list(""), None);
+    }
+
+    #[tokio::test]
+    async fn test_setup_cookie_rewrite() {
+        // Little-endian setup for protocol 11.0 with an 18-byte name and a 16-byte cookie
+        let mut sent = vec![b'l', 0, 11, 0, 0, 0, 18, 0, 16, 0, 0, 0];
+        sent.extend_from_slice(MIT_MAGIC_COOKIE.as_bytes());
+        sent.extend_from_slice(&[0, 0]);
+        sent.extend_from_slice(&[7; 16]);
+
+        let setup = read_setup(&mut sent.as_slice()).await.unwrap();
+        assert_eq!(setup.auth_name, MIT_MAGIC_COOKIE.as_bytes());
+        assert_eq!(setup.auth_data, vec![7; 16]);
+        assert_eq!(setup.encode(), sent);
+
+        let stripped = setup.with_auth(b"", b"").encode();
+        assert_eq!(stripped, vec![b'l', 0, 11, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+    }
+}
@@ -1,7 +1,7 @@
 import { useState, useEffect } from 'react';
 import { invoke } from '../../lib/tauri';
 import { useTerminalStore } from '../../stores/useTerminalStore';
-import { SSHSession } from '../../types';
+import { ForwardKind, KeepaliveSettings, LoginStep, PortForward, SSHSession, TerminalMode } from '../../types';
 
 interface SessionManagerProps {
   onClose: () => void;
@@ -13,11 +13,78 @@ const initialFormData = {
   host: '',
   port: '22',
   username: '',
-  authType: 'password' as 'password' | 'key',
+  authType: 'password' as 'password' | 'key' | 'agent' | 'keyboard-interactive',
   password: '',
   privateKey: '',
+  agentIdentity: '',
+  jumpHosts: [] as string[],
+  portForwards: [] as PortForward[],
+  keepalive: {
+    intervalSecs: 30,
+    maxMissed: 3,
+    autoReconnect: false,
+    maxReconnectAttempts: 10,
+  } as KeepaliveSettings,
+  forwardAgent: false,
+  forwardX11: false,
+  env: {} as Record<string, string>,
+  term: '',
+  terminalModes: [] as TerminalMode[],
+  loginScript: [] as LoginStep[],
 };
 
+// Редове "ИМЕ=стойност" <-> обект; празните и невалидните редове се пропускат
+const formatPairs = (pairs: [string, string | number][]) => pairs.map(([k, v]) => `${k}=${v}`).join('\n');
+
+const parsePairs = (text: string): [string, string][] =>
+  text
+    .split('\n')
+    .map((line) => line.trim())
+    .filter((line) => line.includes('=') && !line.startsWith('='))
+    .map((line) => {
+      const index = line.indexOf('=');
+      return [line.slice(0, index).trim(), line.slice(index + 1)];
+    });
+
+// Скриптът след вход е по една стъпка на ред: "expect ШАБЛОН => ОТГОВОР" чака
+// шаблона (regex) и изпраща отговора, а всеки друг ред е команда
+const EXPECT_PREFIX = 'expect ';
+const EXPECT_SEND = ' => ';
+
+const formatLoginScript = (steps: LoginStep[]) =>
+  steps
+    .map((step) =>
+      step.kind === 'command'
+        ? step.command
+        : `${EXPECT_PREFIX}${step.pattern}${step.send ? `${EXPECT_SEND}${step.send}` : ''}`
+    )
+    .join('\n');
+
+const parseLoginScript = (text: string): LoginStep[] =>
+  text
+    .split('\n')
+    .filter((line) => line.trim() !== '')
+    .map((line): LoginStep => {
+      if (!line.startsWith(EXPECT_PREFIX)) {
+        return { kind: 'command', command: line };
+      }
+      const rest = line.slice(EXPECT_PREFIX.length);
+      const index = rest.indexOf(EXPECT_SEND);
+      return index === -1
+        ? { kind: 'expect', pattern: rest }
+        : { kind: 'expect', pattern: rest.slice(0, index), send: rest.slice(index + EXPECT_SEND.length) };
+    });
+
+const newPortForward = (): PortForward => ({
+  id: '',
+  kind: 'local',
+  bindAddress: '127.0.0.1',
+  bindPort: 8080,
+  targetHost: 'localhost',
+  targetPort: 80,
+  autoStart: true,
+});
+
 export default function SessionManager({ onClose }: SessionManagerProps) {
   const { sessions, setSessions, addTab } = useTerminalStore();
   const [formMode, setFormMode] = useState<'list' | 'create' | 'edit'>('list');
@@ -78,6 +145,31 @@ export default function SessionManager({ onClose }: SessionManagerProps) {
     }
   };
 
+  // Редът на избиране определя реда на прескачане през jump host-овете
+  const toggleJumpHost = (sessionId: string) => {
+    const current: string[] = formData.jumpHosts ?? [];
+    const jumpHosts = current.includes(sessionId)
+      ? current.filter((id) => id !== sessionId)
+      : [...current, sessionId];
+    setFormData({ ...formData, jumpHosts });
+  };
+
+  const updatePortForward = (index: number, changes: Partial<PortForward>) => {
+    const portForwards = (formData.portForwards ?? []).map((f: PortForward, i: number) =>
+      i === index ? { ...f, ...changes } : f
+    );
+    setFormData({ ...formData, portForwards });
+  };
+
+  const removePortForward = (index: number) => {
+    const portForwards = (formData.portForwards ?? []).filter((_: PortForward, i: number) => i !== index);
+    setFormData({ ...formData, portForwards });
+  };
+
+  const updateKeepalive = (changes: Partial<KeepaliveSettings>) => {
+    setFormData({ ...formData, keepalive: { ...initialFormData.keepalive, ...formData.keepalive, ...changes } });
+  };
+
   const handleDeleteSession = async (sessionId: string) => {
     if (window.confirm('Are you sure you want to delete this session?')) {
       try {
@@ -189,11 +281,13 @@ export default function SessionManager({ onClose }: SessionManagerProps) {
         <label className="block text-sm font-medium text-dark-text mb-1">Auth Type</label>
         <select
           value={formData.authType}
-          onChange={(e) => setFormData({ ...formData, authType: e.target.value as 'password' | 'key' })}
+          onChange={(e) => setFormData({ ...formData, authType: e.target.value as 'password' | 'key' | 'agent' | 'keyboard-interactive' })}
           className="w-full px-3 py-2 bg-dark-bg text-dark-text rounded border border-dark-highlight focus:border-dark-cyan outline-none"
         >
           <option value="password">Password</option>
           <option value="key">Private Key</option>
+          <option value="agent">SSH Agent</option>
+          <option value="keyboard-interactive">Keyboard-Interactive (OTP / 2FA)</option>
         </select>
       </div>
 
@@ -209,6 +303,19 @@ export default function SessionManager({ onClose }: SessionManagerProps) {
             className="w-full px-3 py-2 bg-dark-bg text-dark-text rounded border border-dark-highlight focus:border-dark-cyan outline-none"
           />
         </div>
+      ) : formData.authType === 'keyboard-interactive' ? null : formData.authType === 'agent' ? (
+        <div>
+          <label className="block text-sm font-medium text-dark-text mb-1">
+            Agent Identity (fingerprint or comment, leave blank to try all)
+          </label>
+          <input
+            type="text"
+            value={formData.agentIdentity ?? ''}
+            onChange={(e) => setFormData({ ...formData, agentIdentity: e.target.value })}
+            className="w-full px-3 py-2 bg-dark-bg text-dark-text rounded border border-dark-highlight focus:border-dark-cyan outline-none"
+            placeholder="SHA256:... or user@laptop"
+          />
+        </div>
       ) : (
         <div>
           <label className="block text-sm font-medium text-dark-text mb-1">Private Key Path</label>
@@ -222,6 +329,214 @@ export default function SessionManager({ onClose }: SessionManagerProps) {
         </div>
       )}
 
+      {sessions.some((s) => s.id !== formData.id) && (
+        <div>
+          <label className="block text-sm font-medium text-dark-text mb-1">
+            Jump Hosts (in the order selected)
+          </label>
+          <div className="flex flex-wrap gap-2">
+            {sessions
+              .filter((s) => s.id !== formData.id)
+              .map((s) => {
+                const position = (formData.jumpHosts ?? []).indexOf(s.id);
+                return (
+                  <button
+                    key={s.id}
+                    type="button"
+                    onClick={() => toggleJumpHost(s.id)}
+                    className={`px-3 py-1 rounded text-sm transition-colors ${
+                      position >= 0 ? 'bg-dark-cyan text-dark-bg' : 'bg-dark-bg text-dark-text hover:bg-dark-highlight'
+                    }`}
+                  >
+                    {position >= 0 && `${position + 1}. `}{s.name}
+                  </button>
+                );
+              })}
+          </div>
+        </div>
+      )}
+
+      <div>
+        <label className="block text-sm font-medium text-dark-text mb-1">Port Forwards</label>
+        <div className="space-y-2">
+          {(formData.portForwards ?? []).map((forward: PortForward, index: number) => (
+            <div key={forward.id || index} className="grid grid-cols-12 gap-2 items-center">
+              <select
+                value={forward.kind}
+                onChange={(e) => updatePortForward(index, { kind: e.target.value as ForwardKind })}
+                className="col-span-2 px-2 py-2 bg-dark-bg text-dark-text rounded border border-dark-highlight focus:border-dark-cyan outline-none"
+                title="Local (-L): listen here; Remote (-R): the server listens; Dynamic (-D): SOCKS proxy here"
+              >
+                <option value="local">-L</option>
+                <option value="remote">-R</option>
+                <option value="dynamic">-D</option>
+              </select>
+              <input
+                type="number"
+                value={forward.bindPort}
+                onChange={(e) => updatePortForward(index, { bindPort: parseInt(e.target.value, 10) || 0 })}
+                className="col-span-2 px-3 py-2 bg-dark-bg text-dark-text rounded border border-dark-highlight focus:border-dark-cyan outline-none"
+                title={forward.kind === 'remote' ? 'Port on the server' : 'Local port'}
+              />
+              {forward.kind === 'dynamic' ? (
+                <span className="col-span-5 text-sm text-gray-400">SOCKS4a/5 proxy, DNS resolved on the server</span>
+              ) : (
+                <>
+                  <input
+                    type="text"
+                    value={forward.targetHost}
+                    onChange={(e) => updatePortForward(index, { targetHost: e.target.value })}
+                    className="col-span-3 px-3 py-2 bg-dark-bg text-dark-text rounded border border-dark-highlight focus:border-dark-cyan outline-none"
+                    placeholder={forward.kind === 'remote' ? 'local target host' : 'target host (from the server)'}
+                  />
+                  <input
+                    type="number"
+                    value={forward.targetPort}
+                    onChange={(e) => updatePortForward(index, { targetPort: parseInt(e.target.value, 10) || 0 })}
+                    className="col-span-2 px-3 py-2 bg-dark-bg text-dark-text rounded border border-dark-highlight focus:border-dark-cyan outline-none"
+                    title="Target port"
+                  />
+                </>
+              )}
+              <label className="col-span-2 flex items-center gap-1 text-sm text-dark-text">
+                <input
+                  type="checkbox"
+                  checked={forward.autoStart}
+                  onChange={(e) => updatePortForward(index, { autoStart: e.target.checked })}
+                />
+                Auto
+              </label>
+              <button
+                type="button"
+                onClick={() => removePortForward(index)}
+                className="col-span-1 text-dark-red hover:text-opacity-80"
+              >
+                ✕
+              </button>
+            </div>
+          ))}
+          <button
+            type="button"
+            onClick={() =>
+              setFormData({ ...formData, portForwards: [...(formData.portForwards ?? []), newPortForward()] })
+            }
+            className="px-3 py-1 rounded text-sm bg-dark-bg text-dark-text hover:bg-dark-highlight transition-colors"
+          >
+            + Add Forward
+          </button>
+        </div>
+      </div>
+
+      <label
+        className="flex items-center gap-2 text-sm text-dark-text"
+        title="Lets the server use your local ssh-agent keys while connected. Only enable for hosts you trust."
+      >
+        <input
+          type="checkbox"
+          checked={formData.forwardAgent ?? false}
+          onChange={(e) => setFormData({ ...formData, forwardAgent: e.target.checked })}
+        />
+        Forward ssh-agent (-A)
+      </label>
+
+      <label
+        className="flex items-center gap-2 text-sm text-dark-text"
+        title="Shows remote GUI programs on your local X display ($DISPLAY)"
+      >
+        <input
+          type="checkbox"
+          checked={formData.forwardX11 ?? false}
+          onChange={(e) => setFormData({ ...formData, forwardX11: e.target.checked })}
+        />
+        Forward X11 (-X)
+      </label>
+
+      <div className="grid grid-cols-3 gap-2">
+        <div>
+          <label className="block text-sm font-medium text-dark-text mb-1">TERM</label>
+          <input
+            type="text"
+            value={formData.term ?? ''}
+            onChange={(e) => setFormData({ ...formData, term: e.target.value })}
+            className="w-full px-3 py-2 bg-dark-bg text-dark-text rounded border border-dark-highlight focus:border-dark-cyan outline-none"
+            placeholder="xterm-256color"
+          />
+        </div>
+        <div>
+          <label className="block text-sm font-medium text-dark-text mb-1">Environment</label>
+          <textarea
+            rows={3}
+            defaultValue={formatPairs(Object.entries(formData.env ?? {}))}
+            onBlur={(e) => setFormData({ ...formData, env: Object.fromEntries(parsePairs(e.target.value)) })}
+            className="w-full px-3 py-2 bg-dark-bg text-dark-text rounded border border-dark-highlight focus:border-dark-cyan outline-none font-mono text-sm"
+            placeholder={'LANG=bg_BG.UTF-8\nLC_ALL=bg_BG.UTF-8'}
+            title="Sent before the shell starts; the server must allow them with AcceptEnv"
+          />
+        </div>
+        <div>
+          <label className="block text-sm font-medium text-dark-text mb-1">Terminal Modes</label>
+          <textarea
+            rows={3}
+            defaultValue={formatPairs((formData.terminalModes ?? []).map((m: TerminalMode) => [m.name, m.value]))}
+            onBlur={(e) =>
+              setFormData({
+                ...formData,
+                terminalModes: parsePairs(e.target.value).map(([name, value]) => ({
+                  name: name.toUpperCase(),
+                  value: parseInt(value, 10) || 0,
+                })),
+              })
+            }
+            className="w-full px-3 py-2 bg-dark-bg text-dark-text rounded border border-dark-highlight focus:border-dark-cyan outline-none font-mono text-sm"
+            placeholder={'VERASE=127\nECHO=1'}
+          />
+        </div>
+      </div>
+
+      <div>
+        <label className="block text-sm font-medium text-dark-text mb-1">Login Script</label>
+        <textarea
+          rows={3}
+          defaultValue={formatLoginScript(formData.loginScript ?? [])}
+          onBlur={(e) => setFormData({ ...formData, loginScript: parseLoginScript(e.target.value) })}
+          className="w-full px-3 py-2 bg-dark-bg text-dark-text rounded border border-dark-highlight focus:border-dark-cyan outline-none font-mono text-sm"
+          placeholder={'expect \\$ $\ncd /srv/app\nexpect Continue\\? => y'}
+          title="Runs in the shell after login, one step per line. 'expect PATTERN => REPLY' waits for the regex and types the reply; other lines are typed as commands."
+        />
+      </div>
+
+      <div>
+        <label className="block text-sm font-medium text-dark-text mb-1">Keepalive</label>
+        <div className="grid grid-cols-12 gap-2 items-center">
+          <input
+            type="number"
+            min={0}
+            value={formData.keepalive?.intervalSecs ?? 30}
+            onChange={(e) => updateKeepalive({ intervalSecs: parseInt(e.target.value, 10) || 0 })}
+            className="col-span-2 px-3 py-2 bg-dark-bg text-dark-text rounded border border-dark-highlight focus:border-dark-cyan outline-none"
+            title="Seconds between keepalives when idle (0 = off)"
+          />
+          <span className="col-span-1 text-sm text-gray-400">sec</span>
+          <input
+            type="number"
+            min={1}
+            value={formData.keepalive?.maxMissed ?? 3}
+            onChange={(e) => updateKeepalive({ maxMissed: parseInt(e.target.value, 10) || 1 })}
+            className="col-span-2 px-3 py-2 bg-dark-bg text-dark-text rounded border border-dark-highlight focus:border-dark-cyan outline-none"
+            title="Missed replies before the connection is considered dead"
+          />
+          <span className="col-span-2 text-sm text-gray-400">missed max</span>
+          <label className="col-span-5 flex items-center gap-1 text-sm text-dark-text">
+            <input
+              type="checkbox"
+              checked={formData.keepalive?.autoReconnect ?? false}
+              onChange={(e) => updateKeepalive({ autoReconnect: e.target.checked })}
+            />
+            Reconnect automatically
+          </label>
+        </div>
+      </div>
+
       <div className="flex gap-2 pt-4">
         <button
           onClick={handleSaveSession}
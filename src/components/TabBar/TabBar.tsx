@@ -1,4 +1,5 @@
 import { useTerminalStore } from '../../stores/useTerminalStore';
+import { invoke } from '../../lib/tauri';
 
 export default function TabBar() {
   const { tabs, setActiveTab, removeTab, addTab } = useTerminalStore();
@@ -9,6 +10,10 @@ export default function TabBar() {
 
   const handleCloseTab = (tabId: string, e: React.MouseEvent) => {
     e.stopPropagation();
+    // Затваряме SSH сесията, а не само таба
+    invoke('close_terminal', { tabId }).catch((err) => {
+      console.error('Failed to close terminal:', err);
+    });
     removeTab(tabId);
   };
 
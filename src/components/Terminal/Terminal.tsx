@@ -3,7 +3,10 @@ import { Terminal as XTerm } from '@xterm/xterm';
 import { FitAddon } from '@xterm/addon-fit';
 import { WebLinksAddon } from '@xterm/addon-web-links';
 import '@xterm/xterm/css/xterm.css';
-import { invoke, listen } from '../../lib/tauri';
+import { Channel } from '@tauri-apps/api/core';
+import { CommandError, invoke, listen } from '../../lib/tauri';
+import { describeSSHError } from '../../lib/sshErrors';
+import { HostKeyPrompt, KeyboardInteractivePrompt, KeyPassphrasePrompt, LifecycleEvent, SSHErrorInfo, TerminalOutputMessage } from '../../types';
 
 interface TerminalProps {
   tabId: string;
@@ -17,6 +20,11 @@ export default function Terminal({ tabId, sessionId, onConnectionChange }: Termi
   const fitAddonRef = useRef<FitAddon | null>(null);
   const isConnectedRef = useRef(false);
 
+  const pixelSize = () => ({
+    pixelW: terminalRef.current?.clientWidth ?? 0,
+    pixelH: terminalRef.current?.clientHeight ?? 0,
+  });
+
   useEffect(() => {
     if (!terminalRef.current) return;
 
@@ -71,16 +79,6 @@ export default function Terminal({ tabId, sessionId, onConnectionChange }: Termi
     };
     window.addEventListener('resize', handleResize);
 
-    // Слушаме за данни от терминала
-    const unlistenData = listen<{ tab_id: string; data: string }>(
-      'terminal-data',
-      (event) => {
-        if (event.payload.tab_id === tabId) {
-          xterm.write(event.payload.data);
-        }
-      }
-    );
-
     // Слушаме за статус на връзката
     const unlistenStatus = listen<{ tab_id: string; connected: boolean; error?: string }>(
       'connection-status',
@@ -88,14 +86,109 @@ export default function Terminal({ tabId, sessionId, onConnectionChange }: Termi
         if (event.payload.tab_id === tabId) {
           isConnectedRef.current = event.payload.connected;
           onConnectionChange?.(event.payload.connected);
+        }
+      }
+    );
 
-          if (event.payload.error) {
-            xterm.write(`\r\n\x1b[31mError: ${event.payload.error}\x1b[0m\r\n`);
+    // Показваме докъде е стигнала връзката, а при грешка - какво да се провери
+    const unlistenLifecycle = listen<LifecycleEvent>('connection-lifecycle', (event) => {
+      const step = event.payload;
+      if (step.tab_id !== tabId) return;
+
+      switch (step.stage) {
+        case 'resolving':
+          xterm.write(`\x1b[2mResolving ${step.host}...\x1b[0m\r\n`);
+          break;
+        case 'connecting':
+          xterm.write(`\x1b[2mConnecting to ${step.address}...\x1b[0m\r\n`);
+          break;
+        case 'authenticating':
+          xterm.write(`\x1b[2mAuthenticating to ${step.host} (${step.method})...\x1b[0m\r\n`);
+          break;
+        case 'closed':
+          if (step.exit_signal) {
+            xterm.write(`\r\n\x1b[33m[Killed by signal ${step.exit_signal}]\x1b[0m\r\n`);
+          } else if (step.exit_status !== null && step.exit_status !== 0) {
+            xterm.write(`\r\n\x1b[33m[Exited with code ${step.exit_status}]\x1b[0m\r\n`);
+          } else if (step.reason === 'connectionLost') {
+            xterm.write('\r\n\x1b[31m[Connection lost]\x1b[0m\r\n');
           }
-        }
+          break;
+        case 'failed':
+          xterm.write(`\r\n\x1b[31mError: ${describeSSHError(step.error)}\x1b[0m\r\n`);
+          break;
+      }
+    });
+
+    // Скриптът след вход спира при първата неуспешна стъпка
+    const unlistenLoginScript = listen<{ tab_id: string; step: number; error: SSHErrorInfo }>(
+      'login-script-error',
+      (event) => {
+        if (event.payload.tab_id !== tabId) return;
+        const error = describeSSHError(event.payload.error);
+        xterm.write(`\r\n\x1b[33m[Login script stopped at step ${event.payload.step + 1}] ${error}\x1b[0m\r\n`);
       }
     );
 
+    // Питаме потребителя дали да се довери на непознат host key
+    const unlistenHostKey = listen<HostKeyPrompt>('host-key-prompt', (event) => {
+      const prompt = event.payload;
+      if (prompt.tab_id !== tabId) return;
+
+      const accept = window.confirm(
+        `The authenticity of host '${prompt.host}:${prompt.port}' can't be established.\n` +
+        `${prompt.key_type} key fingerprint is ${prompt.fingerprint}.\n\n` +
+        'Are you sure you want to continue connecting?'
+      );
+      invoke('accept_host_key', { promptId: prompt.prompt_id, accept }).catch((err) => {
+        console.error('Failed to answer host key prompt:', err);
+      });
+    });
+
+    // Искаме паролата на криптиран private key
+    const unlistenPassphrase = listen<KeyPassphrasePrompt>('key-passphrase-prompt', (event) => {
+      const prompt = event.payload;
+      if (prompt.tab_id !== tabId) return;
+
+      const message = `${prompt.error ? `${prompt.error}. ` : ''}Enter passphrase for key '${prompt.key_path}':`;
+      const passphrase = window.prompt(message);
+      const remember = passphrase !== null && window.confirm('Remember this passphrase for the session?');
+      invoke('submit_key_passphrase', { promptId: prompt.prompt_id, passphrase, remember }).catch((err) => {
+        console.error('Failed to submit passphrase:', err);
+      });
+    });
+
+    // Keyboard-interactive (PAM, OTP кодове): питаме за всеки prompt от сървъра
+    const unlistenKbdInteractive = listen<KeyboardInteractivePrompt>('keyboard-interactive-prompt', (event) => {
+      const request = event.payload;
+      if (request.tab_id !== tabId) return;
+
+      const header = [request.name, request.instructions].filter(Boolean).join('\n');
+      const responses: string[] = [];
+      let cancelled = false;
+      for (const { prompt } of request.prompts) {
+        const answer = window.prompt(header ? `${header}\n\n${prompt}` : prompt);
+        if (answer === null) {
+          cancelled = true;
+          break;
+        }
+        responses.push(answer);
+      }
+      invoke('answer_keyboard_interactive', {
+        promptId: request.prompt_id,
+        responses: cancelled ? null : responses,
+      }).catch((err) => {
+        console.error('Failed to answer keyboard-interactive prompt:', err);
+      });
+    });
+
+    // Уведомяваме сървъра за новия размер, за да се рендерират vim/htop правилно
+    xterm.onResize(({ cols, rows }) => {
+      invoke('resize_terminal', { tabId, cols, rows, ...pixelSize() }).catch((err) => {
+        console.error('Failed to resize terminal:', err);
+      });
+    });
+
     // Изпращаме данни към SSH сървъра
     xterm.onData((data) => {
       // Винаги изпращаме - backend ще игнорира ако няма връзка
@@ -112,8 +205,12 @@ export default function Terminal({ tabId, sessionId, onConnectionChange }: Termi
 
     return () => {
       window.removeEventListener('resize', handleResize);
-      unlistenData.then((fn) => fn());
       unlistenStatus.then((fn) => fn());
+      unlistenLifecycle.then((fn) => fn());
+      unlistenLoginScript.then((fn) => fn());
+      unlistenHostKey.then((fn) => fn());
+      unlistenPassphrase.then((fn) => fn());
+      unlistenKbdInteractive.then((fn) => fn());
       xterm.dispose();
     };
   }, [tabId]);
@@ -123,13 +220,36 @@ export default function Terminal({ tabId, sessionId, onConnectionChange }: Termi
       // Свързваме се към сесията
       xtermRef.current.write('\r\nConnecting to SSH...\r\n');
 
-      invoke('connect_ssh', { tabId, sessionId })
+      const xterm = xtermRef.current;
+      const size = { cols: xterm.cols, rows: xterm.rows, ...pixelSize() };
+
+      // Подреден канал само за този таб; потвърждаваме всяка порция след като
+      // xterm я изрисува, за да може backend-ът да забави четенето при нужда
+      const onOutput = new Channel<TerminalOutputMessage>();
+      onOutput.onmessage = (message) => {
+        const ack = () => {
+          invoke('ack_terminal_output', { tabId, bytes: message.bytes }).catch((err) => {
+            console.error('Failed to acknowledge output:', err);
+          });
+        };
+
+        if (message.encoding === 'base64') {
+          xterm.write(Uint8Array.from(atob(message.data as string), (c) => c.charCodeAt(0)), ack);
+        } else if (message.encoding === 'bytes') {
+          xterm.write(new Uint8Array(message.data as number[]), ack);
+        } else {
+          xterm.write(message.data as string, ack);
+        }
+      };
+
+      invoke('connect_ssh', { tabId, sessionId, size, onOutput })
         .then(() => {
           isConnectedRef.current = true;
           onConnectionChange?.(true);
         })
         .catch((error) => {
-          xtermRef.current?.write(`\r\n\x1b[31mConnection failed: ${error}\x1b[0m\r\n`);
+          const message = error instanceof CommandError ? describeSSHError(error.info) : String(error);
+          xtermRef.current?.write(`\r\n\x1b[31mConnection failed: ${message}\x1b[0m\r\n`);
           isConnectedRef.current = false;
           onConnectionChange?.(false);
         });
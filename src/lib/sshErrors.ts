@@ -0,0 +1,26 @@
+import { SSHErrorInfo } from '../types';
+
+// Подсказки за грешките по техния стабилен код; текстът от backend-а остава за детайлите
+const hints: Partial<Record<SSHErrorInfo['code'], string>> = {
+  dns: 'Check the host name or your DNS settings.',
+  connection_refused: 'Nothing is listening on that port. Is the SSH server running?',
+  timeout: 'The host did not answer in time. Check the network, VPN or firewall.',
+  connection_reset: 'The connection was dropped by the host or something in between.',
+  disconnected: 'The server closed the connection.',
+  auth_failed: 'Check the username and credentials for this session.',
+  wrong_passphrase: 'The private key passphrase is wrong.',
+  invalid_key: 'The private key could not be read. Check the path and format.',
+  agent: 'Is ssh-agent running and does it hold the key?',
+  host_key_mismatch: 'The host key changed. This may be an attack; verify it before editing known_hosts.',
+  host_key_revoked: 'This host key is marked as revoked in known_hosts.',
+  host_key_rejected: 'The host key was not accepted.',
+  cancelled: 'The login prompt was cancelled.',
+  login_script: "Check the session's login script.",
+};
+
+// Четим текст за терминала: съобщението, подсказка и при jump host - причината
+export function describeSSHError(error: SSHErrorInfo): string {
+  const root = error.code === 'jump_host' && error.cause ? error.cause : error;
+  const hint = hints[root.code];
+  return hint ? `${error.message}\r\n${hint}` : error.message;
+}
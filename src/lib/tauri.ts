@@ -1,5 +1,21 @@
 import { invoke as tauriInvoke } from '@tauri-apps/api/core';
 import { listen as tauriListen } from '@tauri-apps/api/event';
+import { SSHErrorInfo } from '../types';
+
+// Грешка, върната от команда: { code, message } от backend-а, за describeSSHError
+export class CommandError extends Error {
+  info: SSHErrorInfo;
+
+  constructor(info: SSHErrorInfo) {
+    super(info.message);
+    this.name = 'CommandError';
+    this.info = info;
+  }
+}
+
+function isErrorInfo(error: unknown): error is SSHErrorInfo {
+  return typeof error === 'object' && error !== null && 'code' in error && 'message' in error;
+}
 
 // Wrapper за invoke функцията
 export async function invoke<T = any>(cmd: string, args?: Record<string, any>): Promise<T> {
@@ -7,6 +23,9 @@ export async function invoke<T = any>(cmd: string, args?: Record<string, any>):
     return await tauriInvoke(cmd, args);
   } catch (error) {
     console.error(`Error invoking command '${cmd}':`, error);
+    if (isErrorInfo(error)) {
+      throw new CommandError(error);
+    }
     throw new Error(`Tauri API invoke failed for command: ${cmd}`);
   }
 }
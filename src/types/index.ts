@@ -1,12 +1,62 @@
+export type AuthMethod = 'password' | 'publickey' | 'agent' | 'keyboard-interactive';
+
+export type ForwardKind = 'local' | 'remote' | 'dynamic';
+
+export interface PortForward {
+  id: string;
+  kind: ForwardKind;
+  bindAddress: string;
+  bindPort: number;
+  targetHost: string;
+  targetPort: number;
+  autoStart: boolean;
+}
+
+export interface ForwardStatus {
+  forward: PortForward;
+  localAddr: string;
+  bytesSent: number;
+  bytesReceived: number;
+  activeConnections: number;
+  totalConnections: number;
+}
+
+export interface TerminalMode {
+  name: string;
+  value: number;
+}
+
+export interface KeepaliveSettings {
+  intervalSecs: number;
+  maxMissed: number;
+  autoReconnect: boolean;
+  maxReconnectAttempts: number;
+}
+
+export type LoginStep =
+  | { kind: 'command'; command: string }
+  | { kind: 'expect'; pattern: string; send?: string; timeoutMs?: number };
+
 export interface SSHSession {
   id: string;
   name: string;
   host: string;
   port: number;
   username: string;
-  authType: 'password' | 'key';
+  authType: 'password' | 'key' | 'agent' | 'keyboard-interactive';
   password?: string;
   privateKey?: string;
+  agentIdentity?: string;
+  authMethods?: AuthMethod[];
+  jumpHosts?: string[];
+  portForwards?: PortForward[];
+  keepalive?: KeepaliveSettings;
+  forwardAgent?: boolean;
+  forwardX11?: boolean;
+  env?: Record<string, string>;
+  term?: string;
+  terminalModes?: TerminalMode[];
+  loginScript?: LoginStep[];
   group?: string;
   createdAt: string;
   updatedAt: string;
@@ -24,8 +74,270 @@ export interface TerminalData {
   data: string;
 }
 
+export type OutputMode = 'text' | 'base64' | 'bytes';
+
+export interface TerminalOutputMessage {
+  data: string | number[];
+  encoding: OutputMode;
+  bytes: number;
+}
+
+export type EndReason = 'closed' | 'closedByUser' | 'connectionLost' | 'failed';
+
+export type SSHErrorCode =
+  | 'disconnected'
+  | 'timeout'
+  | 'auth_failed'
+  | 'protocol'
+  | 'invalid_key'
+  | 'connection_refused'
+  | 'connection_reset'
+  | 'not_found'
+  | 'permission_denied'
+  | 'io'
+  | 'connection_failed'
+  | 'dns'
+  | 'host_key_mismatch'
+  | 'host_key_revoked'
+  | 'host_key_rejected'
+  | 'cancelled'
+  | 'unsupported'
+  | 'agent'
+  | 'wrong_passphrase'
+  | 'database'
+  | 'port_forward'
+  | 'login_script'
+  | 'jump_host'
+  | 'sftp'
+  | 'transfer'
+  | 'automation_step';
+
+export interface SSHErrorInfo {
+  code: SSHErrorCode;
+  message: string;
+  // Само при jump_host: кой hop се е провалил и защо
+  hop?: number;
+  cause?: SSHErrorInfo;
+  // Само при automation_step: стъпката от скрипта, от 0
+  step?: number;
+}
+
+export type LifecycleEvent = { tab_id: string } & (
+  | { stage: 'resolving'; host: string; port: number }
+  | { stage: 'connecting'; host: string; address: string }
+  | { stage: 'handshaking'; host: string }
+  | { stage: 'authenticating'; host: string; method: AuthMethod }
+  | { stage: 'opening_channel' }
+  | { stage: 'ready'; restored: boolean }
+  | { stage: 'closed'; reason: EndReason; exit_status: number | null; exit_signal: string | null }
+  | { stage: 'failed'; error: SSHErrorInfo }
+);
+
 export interface ConnectionStatus {
   tabId: string;
   connected: boolean;
   error?: string;
+  reason?: EndReason;
+  restored?: boolean;
+  reconnecting?: boolean;
+  attempt?: number;
+  retryInMs?: number;
+  exitStatus?: number;
+  exitSignal?: string;
+  coreDumped?: boolean;
+  errorCode?: SSHErrorCode;
+}
+
+export interface TabExit {
+  exitStatus: number | null;
+  exitSignal: string | null;
+  coreDumped: boolean;
+  errorMessage: string | null;
+  reason: EndReason;
+  error: string | null;
+  errorCode: SSHErrorCode | null;
+  endedAt: string;
+}
+
+export interface HostKeyPrompt {
+  prompt_id: string;
+  tab_id: string;
+  host: string;
+  port: number;
+  key_type: string;
+  fingerprint: string;
+}
+
+export interface KeyPassphrasePrompt {
+  prompt_id: string;
+  tab_id: string;
+  key_path: string;
+  attempt: number;
+  error?: string;
+}
+
+export interface KnownHostEntry {
+  line: number;
+  hosts?: string;
+  hashed: boolean;
+  marker?: string;
+  keyType: string;
+  fingerprint: string;
+}
+
+export interface AgentIdentity {
+  keyType: string;
+  fingerprint: string;
+  comment: string;
+}
+
+export interface KeyboardInteractivePrompt {
+  prompt_id: string;
+  tab_id: string;
+  name: string;
+  instructions: string;
+  prompts: { prompt: string; echo: boolean }[];
+}
+
+export type SftpEntryKind = 'file' | 'directory' | 'symlink' | 'other';
+
+export interface SftpEntry {
+  name: string;
+  path: string;
+  kind: SftpEntryKind;
+  size: number;
+  mode: number;
+  mtime?: number;
+  uid?: number;
+  gid?: number;
+  owner?: string;
+  group?: string;
+  symlinkTarget?: string;
+}
+
+export type TransferDirection = 'upload' | 'download';
+
+export type TransferState = 'queued' | 'running' | 'paused' | 'completed' | 'failed' | 'cancelled';
+
+export interface TransferRequest {
+  direction: TransferDirection;
+  localPath: string;
+  remotePath: string;
+  verifyChecksum?: boolean;
+}
+
+export interface TransferInfo {
+  id: string;
+  tabId: string;
+  direction: TransferDirection;
+  localPath: string;
+  remotePath: string;
+  verifyChecksum: boolean;
+  state: TransferState;
+  bytes: number;
+  total?: number;
+  error?: string;
+  resumeFrom?: number;
+}
+
+export interface TransferProgress {
+  transfer_id: string;
+  tab_id: string;
+  bytes: number;
+  total: number;
+  rate: number;
+  eta_secs?: number;
+}
+
+export interface SyncRequest {
+  direction: TransferDirection;
+  localDir: string;
+  remoteDir: string;
+  compare?: 'sizeMtime' | 'hash';
+  delete?: boolean;
+}
+
+export type SyncActionKind = 'createDir' | 'create' | 'update' | 'delete' | 'deleteDir';
+
+export interface SyncAction {
+  kind: SyncActionKind;
+  path: string;
+  size: number;
+}
+
+export interface SyncPlan {
+  actions: SyncAction[];
+  bytes: number;
+}
+
+export interface SyncProgress {
+  sync_id: string;
+  tab_id: string;
+  action: number;
+  actions: number;
+  kind?: SyncActionKind;
+  path?: string;
+  bytes: number;
+  total: number;
+  done: boolean;
+}
+
+export interface ExecResult {
+  stdout: string;
+  stderr: string;
+  exitStatus?: number;
+  exitSignal?: string;
+  timedOut: boolean;
+  truncated: boolean;
+  durationMs: number;
+}
+
+export interface ExpectBranch {
+  pattern: string;
+  capture?: string;
+  goto?: string;
+}
+
+export type AutomationStep =
+  | { kind: 'send'; text: string; enter?: boolean }
+  | { kind: 'expect'; branches: ExpectBranch[]; timeoutMs?: number; onTimeout?: string }
+  | { kind: 'label'; name: string }
+  | { kind: 'goto'; label: string }
+  | { kind: 'sleep'; ms: number }
+  | { kind: 'fail'; message: string };
+
+export interface AutomationScript {
+  id: string;
+  name: string;
+  description?: string;
+  steps: AutomationStep[];
+  createdAt: string;
+  updatedAt: string;
+}
+
+export type AutomationRunState = 'running' | 'succeeded' | 'failed' | 'cancelled';
+
+export interface AutomationLogEntry {
+  step: number;
+  at: string;
+  message: string;
+}
+
+export interface AutomationRun {
+  id: string;
+  scriptId: string;
+  scriptName: string;
+  tabId: string;
+  state: AutomationRunState;
+  log: AutomationLogEntry[];
+  variables: Record<string, string>;
+  error?: string;
+  startedAt: string;
+  finishedAt?: string;
+}
+
+export interface AutomationStepEvent {
+  run_id: string;
+  tab_id: string;
+  entry: AutomationLogEntry;
 }